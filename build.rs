@@ -1,13 +1,65 @@
 use anyhow::Result;
-use std::process::Command;
+use std::{
+    env::var,
+    fs::read_to_string,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub fn main() -> Result<()> {
-    // Retrieve the version from git
-    let version = Command::new("git").arg("describe").arg("--tags").output()?;
+    // Retrieve the version from git, falling back to a VERSION file or the
+    // crate version when building outside of a git checkout
+    let version = git(&["describe", "--tags"])
+        .or_else(|| version_file(&var("CARGO_MANIFEST_DIR").ok()?))
+        .unwrap_or_else(|| env!("CARGO_PKG_VERSION").into());
+    println!("cargo:rustc-env=VERSION={}", version);
+
+    // Retrieve the commit from git
+    let commit = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+
+    // Prefer the timestamp of reproducible builds over the current time
+    let timestamp = match var("SOURCE_DATE_EPOCH") {
+        Ok(timestamp) => timestamp,
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs()
+            .to_string(),
+    };
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+
+    // Retrieve the version of the compiler building pinns
+    let rustc = Command::new(var("RUSTC")?).arg("--version").output()?;
     println!(
-        "cargo:rustc-env=VERSION={}",
-        String::from_utf8(version.stdout)?
+        "cargo:rustc-env=RUSTC_VERSION={}",
+        String::from_utf8(rustc.stdout)?.trim()
     );
 
     Ok(())
 }
+
+/// Run git with the provided arguments, returning its trimmed output if it
+/// succeeded with any
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    non_empty(String::from_utf8(output.stdout).ok()?)
+}
+
+/// Read the trimmed content of the VERSION file in the directory, if any
+fn version_file(dir: &str) -> Option<String> {
+    non_empty(read_to_string(Path::new(dir).join("VERSION")).ok()?)
+}
+
+/// The trimmed string, unless it is empty
+fn non_empty(s: String) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.into())
+    }
+}
@@ -0,0 +1,153 @@
+//! Dropping of Linux capabilities
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::errno::Errno;
+
+/// The names of all known capabilities, indexed by their number
+const CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// The capability interface version supporting 64 bit capability sets
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+/// The header of the capget(2) and capset(2) system calls
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+/// Each of the 32 bit halves of the capability sets
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Parse a capability name like `CAP_NET_ADMIN` or `net_admin` into its
+/// number
+pub fn parse_capability(s: &str) -> Result<u32> {
+    let name = s.to_uppercase();
+    let name = if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
+    };
+    match CAPABILITIES.iter().position(|x| *x == name) {
+        Some(cap) => Ok(cap as u32),
+        None => bail!("unknown capability {}", s),
+    }
+}
+
+/// Drop the capabilities from the bounding set as well as from the
+/// effective, permitted and inheritable sets of the calling process
+///
+/// Capabilities unknown to the running kernel are skipped.
+pub fn drop_capabilities(caps: &[u32]) -> Result<()> {
+    if caps.is_empty() {
+        return Ok(());
+    }
+
+    let mut known = vec![];
+    for cap in caps {
+        let name = CAPABILITIES[*cap as usize];
+        debug!("dropping {}", name);
+        let res = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, libc::c_ulong::from(*cap), 0, 0, 0) };
+        match Errno::result(res) {
+            Ok(_) => known.push(*cap),
+            Err(e) if e.as_errno() == Some(Errno::EINVAL) => {
+                debug!("{} is not supported by the running kernel", name)
+            }
+            Err(e) => {
+                return Err(e).context(format!("unable to drop {} from the bounding set", name))
+            }
+        }
+    }
+
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    let res = unsafe { libc::syscall(libc::SYS_capget, &mut header as *mut _, data.as_mut_ptr()) };
+    Errno::result(res).context("unable to get capabilities")?;
+
+    for cap in known {
+        let set = &mut data[cap as usize / 32];
+        let bit = 1 << (cap % 32);
+        set.effective &= !bit;
+        set.permitted &= !bit;
+        set.inheritable &= !bit;
+    }
+    let res = unsafe { libc::syscall(libc::SYS_capset, &mut header as *mut _, data.as_ptr()) };
+    Errno::result(res).context("unable to set capabilities")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_capability_success() -> Result<()> {
+        assert_eq!(parse_capability("CAP_CHOWN")?, 0);
+        assert_eq!(parse_capability("net_admin")?, 12);
+        assert_eq!(parse_capability("Cap_Sys_Admin")?, 21);
+        assert_eq!(parse_capability("checkpoint_restore")?, 40);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_capability_failed_unknown() {
+        assert!(parse_capability("CAP_FOO").is_err());
+        assert!(parse_capability("").is_err());
+    }
+
+    #[test]
+    fn drop_capabilities_none() -> Result<()> {
+        drop_capabilities(&[])
+    }
+}
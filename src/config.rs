@@ -1,29 +1,140 @@
 //! Configuration related structures
-use anyhow::{bail, Result};
-use clap::{AppSettings, Clap};
+use crate::{
+    capability::parse_capability, kernel, mountinfo, oci::spec_namespaces, output::OutputFormat,
+    signal,
+};
+use anyhow::{bail, format_err, Context, Error, Result};
+use clap::{App, AppSettings, ArgMatches, Clap, FromArgMatches, IntoApp};
+use clap_generate::{
+    generate,
+    generators::{Bash, Elvish, Fish, PowerShell, Zsh},
+};
 use getset::{CopyGetters, Getters};
 use lazy_static::lazy_static;
 use log::{debug, LevelFilter};
-use nix::sched::CloneFlags;
-use std::{env::temp_dir, fs::create_dir, fs::metadata, path::PathBuf};
+use nix::{
+    fcntl::{fcntl, FcntlArg},
+    mount::{MntFlags, MsFlags},
+    sched::CloneFlags,
+    sys::stat::stat,
+    unistd::{Gid, Uid},
+};
+use schemars::{schema_for, JsonSchema};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::HashSet,
+    env::{args_os, temp_dir, var, var_os, VarError},
+    ffi::OsString,
+    fmt,
+    fs::{create_dir, create_dir_all, metadata, read_link, set_permissions, File, Permissions},
+    io::{stdin, ErrorKind, Read, Write},
+    os::unix::{fs::PermissionsExt, io::RawFd},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use uuid::Uuid;
 
+/// The per-mount flags which can be applied to the bind mounts, by their names
+const MOUNT_FLAGS: &[(&str, MsFlags)] = &[
+    ("nodev", MsFlags::MS_NODEV),
+    ("noexec", MsFlags::MS_NOEXEC),
+    ("nosuid", MsFlags::MS_NOSUID),
+];
+
+/// The clone flag for the time namespace, available since Linux 5.6
+const CLONE_NEWTIME: libc::c_int = 0x0000_0080;
+
+/// The maximum time namespace offset in seconds accepted by the kernel
+const KTIME_SEC_MAX: i64 = i64::max_value() / 1_000_000_000;
+
+/// The file which only exists at the root of a cgroup v2 hierarchy
+const CGROUP_V2_CONTROLLERS: &str = "/sys/fs/cgroup/cgroup.controllers";
+
+/// The maximum hostname length accepted by the kernel
+const HOST_NAME_MAX: usize = 64;
+
+/// The names of all supported namespaces
+pub const NAMESPACE_NAMES: &[&str] = &["cgroup", "ipc", "mnt", "net", "pid", "time", "user", "uts"];
+
+/// The order in which the namespaces get unshared
+///
+/// The user namespace comes first, so that it owns all other new namespaces
+/// and grants the capabilities to create them. A new user namespace gets
+/// unshared together with the remaining ones by a holder process, because
+/// binding them still requires the capabilities of pinns on the host.
+const UNSHARE_ORDER: &[&str] = &["user", "cgroup", "ipc", "mnt", "net", "pid", "time", "uts"];
+
+/// The order in which the namespaces of a process get joined, as done by
+/// nsenter(1)
+///
+/// The user namespace comes first to gain the capabilities for joining the
+/// namespaces it owns, or last to drop the ones for joining all others.
+const SETNS_ORDER: &[&str] = &["user", "cgroup", "ipc", "uts", "net", "pid", "mnt", "time"];
+
+/// The arguments selecting the namespaces to pin, with their environment
+/// variables
+const NAMESPACE_SELECTION: &[(&str, &str)] = &[
+    ("all", "PINNS_ALL"),
+    ("cgroup", "PINNS_CGROUP"),
+    ("ipc", "PINNS_IPC"),
+    ("mnt", "PINNS_MOUNT"),
+    ("net", "PINNS_NET"),
+    ("pid", "PINNS_PID"),
+    ("time", "PINNS_TIME"),
+    ("uts", "PINNS_UTS"),
+    ("user", "PINNS_USER"),
+    ("from-oci-spec", "PINNS_FROM_OCI_SPEC"),
+    ("namespace-list", "PINNS_NAMESPACES"),
+];
+
 lazy_static! {
     static ref TEMP_DIR: String = temp_dir().display().to_string();
     static ref TEMP_FILE: String = Uuid::new_v4().to_hyphenated().to_string();
 }
 
-#[derive(Clap, Getters, CopyGetters)]
+/// Take the options of the config file which have neither been set on the
+/// command line nor via their environment variable
+///
+/// Options which are optional in the configuration as well are listed after
+/// `Some:`. The argument names of clap are the kebab-case field names.
+macro_rules! merge_file_options {
+    ($config:ident, $file:ident, Some: $($field:ident => $env:expr),* $(,)?) => {
+        $(
+            if let Some(value) = $file.$field {
+                if !$config.is_explicit(&stringify!($field).replace('_', "-"), $env) {
+                    $config.$field = Some(value);
+                }
+            }
+        )*
+    };
+    ($config:ident, $file:ident, $($field:ident => $env:expr),* $(,)?) => {
+        $(
+            if let Some(value) = $file.$field {
+                if !$config.is_explicit(&stringify!($field).replace('_', "-"), $env) {
+                    $config.$field = value;
+                }
+            }
+        )*
+    };
+}
+
+#[derive(Clap, Clone, Getters, CopyGetters, Serialize)]
 #[clap(
     after_help("More info at: https://github.com/saschagrunert/pinns.rs"),
-    global_setting(AppSettings::ColoredHelp),
     version(env!("VERSION"))
 )]
 /// A simple utility to pin Linux namespaces
 pub struct Config {
-    #[get_copy = "pub"]
+    #[get = "pub"]
+    #[clap(env("PINNS_CONFIG"), long("config"), value_name("PATH"))]
+    /// A JSON file containing the configuration, which gets overridden by the
+    /// provided command line arguments and environment variables, see
+    /// `config-schema` for the supported options
+    config_file: Option<PathBuf>,
+
     #[clap(
         default_value("info"),
+        env("PINNS_LOG_LEVEL"),
         long("log-level"),
         possible_values(&["trace", "debug", "info", "warn", "error", "off"]),
         short("l"),
@@ -32,17 +143,194 @@ pub struct Config {
     /// The logging level of the application
     log_level: LevelFilter,
 
+    #[get_copy = "pub"]
+    #[clap(long("quiet"), short("q"))]
+    /// Suppress all log output, overrides `log-level`
+    quiet: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("version-json"))]
+    #[serde(skip)]
+    /// Print the version and build information as JSON
+    version_json: bool,
+
+    #[clap(long("no-color"))]
+    /// Disable colored help and log output, which happens as well if the
+    /// `NO_COLOR` environment variable is set
+    no_color: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("text"),
+        env("PINNS_LOG_FORMAT"),
+        long("log-format"),
+        possible_values(&["text", "json"]),
+        value_name("FORMAT")
+    )]
+    /// The format of the log messages
+    log_format: LogFormat,
+
     #[get = "pub"]
-    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    #[clap(env("PINNS_LOG_FILE"), long("log-file"), value_name("PATH"))]
+    /// Append the log messages to the file instead of writing them to stderr
+    log_file: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&TEMP_DIR),
+        env("PINNS_DIR"),
+        long("dir"),
+        short("d"),
+        value_name("DIRECTORY")
+    )]
     /// The parent directory for the pinned namespaces
     /// The final namespace will be pinned to `dir`/`namespace.name`ns/`filename`
     dir: PathBuf,
 
     #[get = "pub"]
-    #[clap(default_value(&TEMP_FILE), long("filename"), short("f"), value_name("FILENAME"))]
-    /// The file name each namespace will be pinned to
+    #[clap(
+        default_value(&TEMP_FILE),
+        env("PINNS_FILENAME"),
+        long("filename"),
+        short("f"),
+        value_name("FILENAME")
+    )]
+    /// The file name each namespace will be pinned to, where `{uuid}` gets
+    /// replaced by a random UUID and `{index}` by the first number resulting
+    /// in not yet existing files, like in the per namespace file names
     filename: String,
 
+    #[clap(env("PINNS_CGROUP_FILENAME"), long("cgroup-filename"), value_name("FILENAME"))]
+    /// The file name the cgroup namespace will be pinned to instead of `filename`
+    cgroup_filename: Option<String>,
+
+    #[clap(env("PINNS_IPC_FILENAME"), long("ipc-filename"), value_name("FILENAME"))]
+    /// The file name the IPC namespace will be pinned to instead of `filename`
+    ipc_filename: Option<String>,
+
+    #[clap(env("PINNS_MOUNT_FILENAME"), long("mount-filename"), value_name("FILENAME"))]
+    /// The file name the mount namespace will be pinned to instead of `filename`
+    mnt_filename: Option<String>,
+
+    #[clap(env("PINNS_NET_FILENAME"), long("net-filename"), value_name("FILENAME"))]
+    /// The file name the network namespace will be pinned to instead of `filename`
+    net_filename: Option<String>,
+
+    #[clap(env("PINNS_PID_FILENAME"), long("pid-filename"), value_name("FILENAME"))]
+    /// The file name the PID namespace will be pinned to instead of `filename`
+    pid_filename: Option<String>,
+
+    #[clap(env("PINNS_TIME_FILENAME"), long("time-filename"), value_name("FILENAME"))]
+    /// The file name the time namespace will be pinned to instead of `filename`
+    time_filename: Option<String>,
+
+    #[clap(env("PINNS_UTS_FILENAME"), long("uts-filename"), value_name("FILENAME"))]
+    /// The file name the UTS namespace will be pinned to instead of `filename`
+    uts_filename: Option<String>,
+
+    #[clap(env("PINNS_USER_FILENAME"), long("user-filename"), value_name("FILENAME"))]
+    /// The file name the user namespace will be pinned to instead of `filename`
+    user_filename: Option<String>,
+
+    #[clap(env("PINNS_CGROUP_DIR"), long("cgroup-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the cgroup namespace instead of `dir`
+    cgroup_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_IPC_DIR"), long("ipc-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the IPC namespace instead of `dir`
+    ipc_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_MOUNT_DIR"), long("mount-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the mount namespace instead of `dir`
+    mnt_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_NET_DIR"), long("net-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the network namespace instead of `dir`
+    net_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_PID_DIR"), long("pid-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the PID namespace instead of `dir`
+    pid_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_TIME_DIR"), long("time-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the time namespace instead of `dir`
+    time_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_UTS_DIR"), long("uts-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the UTS namespace instead of `dir`
+    uts_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_USER_DIR"), long("user-dir"), value_name("DIRECTORY"))]
+    /// The parent directory for the user namespace instead of `dir`
+    user_dir: Option<PathBuf>,
+
+    #[clap(env("PINNS_CGROUP_SOURCE"), long("cgroup-source"), value_name("PATH"))]
+    /// An existing cgroup namespace file to be pinned instead of a new namespace
+    cgroup_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_IPC_SOURCE"), long("ipc-source"), value_name("PATH"))]
+    /// An existing IPC namespace file to be pinned instead of a new namespace
+    ipc_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_MOUNT_SOURCE"), long("mount-source"), value_name("PATH"))]
+    /// An existing mount namespace file to be pinned instead of a new namespace
+    mnt_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_NET_SOURCE"), long("net-source"), value_name("PATH"))]
+    /// An existing network namespace file to be pinned instead of a new namespace
+    net_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_PID_SOURCE"), long("pid-source"), value_name("PATH"))]
+    /// An existing PID namespace file to be pinned instead of a new namespace
+    pid_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_TIME_SOURCE"), long("time-source"), value_name("PATH"))]
+    /// An existing time namespace file to be pinned instead of a new namespace
+    time_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_UTS_SOURCE"), long("uts-source"), value_name("PATH"))]
+    /// An existing UTS namespace file to be pinned instead of a new namespace
+    uts_source: Option<PathBuf>,
+
+    #[clap(env("PINNS_USER_SOURCE"), long("user-source"), value_name("PATH"))]
+    /// An existing user namespace file to be pinned instead of a new namespace
+    user_source: Option<PathBuf>,
+
+    #[clap(
+        env("PINNS_BIND_FD"),
+        long("bind-fd"),
+        parse(try_from_str = parse_bind_fd),
+        use_delimiter(true),
+        value_name("NAMESPACE=FD")
+    )]
+    /// Bind a namespace from an inherited file descriptor instead of a proc
+    /// path, like `net=3`
+    bind_fd: Vec<(String, RawFd)>,
+
+    #[clap(long("unique-per-namespace"))]
+    /// Pin every namespace without a file name override to its own random file
+    /// name instead of `filename`
+    unique_per_namespace: bool,
+
+    #[clap(long("flat"))]
+    /// Pin the namespaces directly into `dir` instead of per namespace
+    /// subdirectories, which requires distinct file names
+    flat: bool,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("ns"),
+        env("PINNS_DIR_SUFFIX"),
+        long("dir-suffix"),
+        value_name("SUFFIX")
+    )]
+    /// The suffix of the per namespace subdirectories, like `ns` for `netns`
+    dir_suffix: String,
+
+    #[clap(long("all"), short("a"))]
+    /// Pin all namespaces supported by the running kernel
+    all: bool,
+
     #[clap(long("cgroup"), short("c"))]
     /// Pin the cgroup namespace
     cgroup: bool,
@@ -51,6 +339,10 @@ pub struct Config {
     /// Pin the IPC namespace
     ipc: bool,
 
+    #[clap(long("mount"), short("m"))]
+    /// Pin the mount namespace
+    mnt: bool,
+
     #[clap(long("net"), short("n"))]
     /// Pin the network namespace
     net: bool,
@@ -59,170 +351,3744 @@ pub struct Config {
     /// Pin the PID namespace
     pid: bool,
 
+    #[clap(long("time"), short("T"))]
+    /// Pin the time namespace
+    time: bool,
+
     #[clap(long("uts"), short("u"))]
     /// Pin the UTS namespace
     uts: bool,
 
+    #[clap(long("user"), short("U"))]
+    /// Pin the user namespace
+    user: bool,
+
+    #[clap(long("from-stdin"))]
+    /// Read a newline or comma separated list of namespaces to pin from stdin
+    from_stdin: bool,
+
+    #[clap(env("PINNS_FROM_OCI_SPEC"), long("from-oci-spec"), value_name("PATH"))]
+    /// Pin the namespaces to be created by an OCI runtime spec like
+    /// `config.json`, which skips the ones with a path
+    from_oci_spec: Option<PathBuf>,
+
+    #[clap(
+        env("PINNS_NAMESPACES"),
+        long("namespaces"),
+        parse(try_from_str = parse_namespace_name),
+        use_delimiter(true),
+        value_name("NAMESPACES")
+    )]
+    /// A comma separated list of namespaces to pin, like `net,uts,pid`
+    namespace_list: Vec<String>,
+
+    #[clap(
+        env("PINNS_DISABLE"),
+        long("disable"),
+        parse(try_from_str = parse_namespace_name),
+        use_delimiter(true),
+        value_name("NAMESPACES")
+    )]
+    /// A comma separated list of namespaces not to pin, even if enabled by
+    /// `all` or any other option
+    disable: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("text"),
+        env("PINNS_OUTPUT_FORMAT"),
+        long("output-format"),
+        possible_values(&["text", "json"]),
+        short("o"),
+        value_name("FORMAT")
+    )]
+    /// The output format of the pinned namespace paths and listings
+    output_format: OutputFormat,
+
+    #[get_copy = "pub"]
+    #[clap(long("include-inode"))]
+    /// Include the inode number of every pinned namespace in the output, like
+    /// `net:[4026531956]`
+    include_inode: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0755"),
+        env("PINNS_DIR_MODE"),
+        long("dir-mode"),
+        parse(try_from_str = parse_mode),
+        value_name("MODE")
+    )]
+    #[serde(serialize_with = "serialize_mode")]
+    /// The octal permissions of the created directories
+    dir_mode: u32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0644"),
+        env("PINNS_FILE_MODE"),
+        long("file-mode"),
+        parse(try_from_str = parse_mode),
+        value_name("MODE")
+    )]
+    #[serde(serialize_with = "serialize_mode")]
+    /// The octal permissions of the created namespace files
+    file_mode: u32,
+
+    #[get_copy = "pub"]
+    #[clap(env("PINNS_UID"), long("uid"), requires("gid"), value_name("UID"))]
+    /// The owning user of the created namespace files
+    uid: Option<u32>,
+
+    #[get_copy = "pub"]
+    #[clap(env("PINNS_GID"), long("gid"), requires("uid"), value_name("GID"))]
+    /// The owning group of the created namespace files
+    gid: Option<u32>,
+
+    #[clap(long("mkdir"))]
+    /// Create missing parent directories of `dir`
+    mkdir: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("no-pin"))]
+    /// Only unshare the namespaces without bind mounting them
+    no_pin: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("force"))]
+    /// Replace already existing namespace files instead of failing
+    force: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("overwrite-existing-empty"))]
+    /// Replace already existing namespace files only if they are empty regular
+    /// files which are not mounted, like the leftovers of a crashed run
+    overwrite_existing_empty: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("new-keyring"))]
+    /// Join a fresh session keyring after unsharing the namespaces
+    new_keyring: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("timing"))]
+    /// Log the duration of unsharing, of binding every namespace and in total
+    timing: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("read-only"))]
+    /// Remount every pinned namespace file read-only after binding it
+    read_only: bool,
+
+    #[clap(
+        env("PINNS_MOUNT_FLAGS"),
+        long("mount-flags"),
+        parse(try_from_str = parse_mount_flag),
+        use_delimiter(true),
+        value_name("FLAGS")
+    )]
+    /// A comma separated list of flags to remount every pinned namespace file
+    /// with after binding it, like `nosuid,noexec,nodev`
+    #[serde(serialize_with = "serialize_mount_flags")]
+    mount_flags: Vec<MsFlags>,
+
+    #[get_copy = "pub"]
+    #[clap(long("verify"))]
+    /// Verify that every bind mount shows up as namespace file system mount
+    verify: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("no-symlinks"))]
+    /// Refuse symlinks within the path of every pinned namespace file below
+    /// the parent directory, which guards against swapping a subdirectory for
+    /// a symlink
+    no_symlinks: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("strict"))]
+    /// Refuse to pin into a directory on a shared mount instead of only
+    /// warning, because the bind mounts would propagate to its peers
+    strict: bool,
+
+    #[clap(long("strict-kernel-check"))]
+    /// Fail upfront with a report of all requested features which are not
+    /// supported by the running kernel
+    strict_kernel_check: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("mount-tmpfs"))]
+    /// Mount a dedicated tmpfs at the empty `dir` before pinning
+    mount_tmpfs: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("print-config"))]
+    #[serde(skip)]
+    /// Print the effective configuration as JSON after merging the
+    /// environment and config file, without pinning anything
+    print_config: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("dry-run"))]
+    /// Print the namespaces to be unshared and bind mounted without doing it
+    dry_run: bool,
+
     #[get = "pub"]
-    #[clap(skip)]
-    namespaces: Namespaces,
-}
+    #[clap(default_value("/proc"), env("PINNS_PROC_PATH"), long("proc-path"), value_name("PATH"))]
+    /// The mount point of the proc file system providing the namespaces
+    proc_path: PathBuf,
 
-#[derive(Getters)]
-pub struct Namespaces {
     #[get = "pub"]
-    cgroup: Namespace,
+    #[clap(env("PINNS_PIN_PATHS_FILE"), long("pin-paths-file"), value_name("PATH"))]
+    /// Atomically write the pinned namespace paths as JSON to the file
+    pin_paths_file: Option<PathBuf>,
 
     #[get = "pub"]
-    ipc: Namespace,
+    #[clap(env("PINNS_METADATA_FILE"), long("metadata-file"), value_name("PATH"))]
+    /// Atomically write a JSON document describing the pins to the file
+    metadata_file: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(long("index"))]
+    /// Record the pinned namespaces in the index `dir`/pinns.json, which lists
+    /// the pins of all runs and gets removed once everything is unpinned
+    index: bool,
 
     #[get = "pub"]
-    net: Namespace,
+    #[clap(env("PINNS_PASS_FD_SOCKET"), long("pass-fd-socket"), value_name("PATH"))]
+    /// Send the file descriptors of the namespaces to the unix socket listening
+    /// at the path
+    pass_fd_socket: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("PINNS_MOUNT_RETRIES"),
+        long("mount-retries"),
+        value_name("COUNT")
+    )]
+    /// The number of retries if bind mounting fails with EBUSY or EAGAIN
+    mount_retries: u32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("100"),
+        env("PINNS_MOUNT_RETRY_DELAY"),
+        long("mount-retry-delay"),
+        value_name("MILLISECONDS")
+    )]
+    /// The initial delay between mount retries, which doubles on every retry
+    mount_retry_delay: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("PINNS_JOBS"),
+        long("jobs"),
+        short("j"),
+        value_name("JOBS")
+    )]
+    /// The number of namespaces to bind concurrently, where `0` binds all at
+    /// once and `1` binds them one after another
+    jobs: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("unchanged"),
+        env("PINNS_PROPAGATION"),
+        long("propagation"),
+        possible_values(&["private", "shared", "slave", "unchanged"]),
+        value_name("PROPAGATION")
+    )]
+    /// The mount propagation of the bind mounted namespaces
+    propagation: Propagation,
 
     #[get = "pub"]
-    pid: Namespace,
+    #[clap(env("PINNS_SELINUX_LABEL"), long("selinux-label"), value_name("CONTEXT"))]
+    /// The SELinux context to be applied to the pinned namespace files, like
+    /// `system_u:object_r:container_file_t:s0`
+    selinux_label: Option<String>,
 
     #[get = "pub"]
-    uts: Namespace,
-}
+    #[clap(env("PINNS_UTS_HOSTNAME"), long("uts-hostname"), value_name("HOSTNAME"))]
+    /// The hostname to be set within the pinned UTS namespace
+    uts_hostname: Option<String>,
 
-impl Default for Namespaces {
-    fn default() -> Self {
-        Namespaces {
-            cgroup: Namespace {
-                name: "cgroup",
-                clone_flag: CloneFlags::CLONE_NEWCGROUP,
-                enabled: false,
-            },
-            ipc: Namespace {
-                name: "ipc",
-                clone_flag: CloneFlags::CLONE_NEWIPC,
-                enabled: false,
-            },
-            net: Namespace {
-                name: "net",
-                clone_flag: CloneFlags::CLONE_NEWNET,
-                enabled: false,
-            },
-            pid: Namespace {
-                name: "pid",
-                clone_flag: CloneFlags::CLONE_NEWPID,
-                enabled: false,
-            },
-            uts: Namespace {
-                name: "uts",
-                clone_flag: CloneFlags::CLONE_NEWUTS,
-                enabled: false,
-            },
-        }
-    }
-}
+    #[get_copy = "pub"]
+    #[clap(
+        env("PINNS_TIME_OFFSET_MONOTONIC"),
+        long("time-offset-monotonic"),
+        value_name("SECONDS"),
+        allow_hyphen_values(true)
+    )]
+    /// The offset of the monotonic clock within the pinned time namespace
+    time_offset_monotonic: Option<i64>,
 
-impl IntoIterator for &Namespaces {
-    type Item = Namespace;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+    #[get_copy = "pub"]
+    #[clap(
+        env("PINNS_TIME_OFFSET_BOOTTIME"),
+        long("time-offset-boottime"),
+        value_name("SECONDS"),
+        allow_hyphen_values(true)
+    )]
+    /// The offset of the boottime clock within the pinned time namespace
+    time_offset_boottime: Option<i64>,
 
-    fn into_iter(self) -> Self::IntoIter {
-        vec![self.cgroup, self.ipc, self.net, self.pid, self.uts].into_iter()
-    }
-}
+    #[clap(long("require-cgroup-v2"))]
+    /// Fail if the pinned cgroup namespace does not use the unified cgroup v2
+    /// hierarchy of the host
+    require_cgroup_v2: bool,
 
-#[derive(Clone, Copy, Getters, CopyGetters)]
-pub struct Namespace {
     #[get = "pub"]
-    name: &'static str,
+    #[clap(env("PINNS_CGROUP_PATH"), long("cgroup-path"), value_name("PATH"))]
+    /// The cgroup v2 directory the process moves into before unsharing, which
+    /// becomes the root of the pinned cgroup namespace
+    cgroup_path: Option<PathBuf>,
 
     #[get_copy = "pub"]
-    enabled: bool,
+    #[clap(env("PINNS_JOIN_PID"), long("join-pid"), value_name("PID"))]
+    /// Join the selected namespaces of the process instead of creating new ones
+    join_pid: Option<i32>,
 
     #[get_copy = "pub"]
-    clone_flag: CloneFlags,
-}
+    #[clap(env("PINNS_PIN_PID"), long("pin-pid"), value_name("PID"))]
+    /// Pin the selected namespaces of the process without creating new ones
+    pin_pid: Option<i32>,
 
-impl Config {
-    /// Validate the configuration in their parameters
-    pub fn validate(&mut self) -> Result<()> {
-        self.namespaces.cgroup.enabled = self.cgroup;
-        self.namespaces.ipc.enabled = self.ipc;
-        self.namespaces.net.enabled = self.net;
-        self.namespaces.pid.enabled = self.pid;
-        self.namespaces.uts.enabled = self.uts;
+    #[clap(long("skip-unchanged"))]
+    /// Skip the namespaces of the joined or pinned process which are the same
+    /// as the own ones
+    skip_unchanged: bool,
 
-        if self.namespaces().into_iter().all(|x| !x.enabled()) {
-            bail!("no namespace specified for pinning")
-        }
+    #[clap(env("PINNS_UID_MAP"), long("uid-map"), value_name("CONTAINER:HOST:SIZE"))]
+    /// The UID mappings of the user namespace, can be specified multiple times
+    uid_map: Vec<String>,
 
-        is_dir_or_create(self.dir())?;
+    #[clap(env("PINNS_GID_MAP"), long("gid-map"), value_name("CONTAINER:HOST:SIZE"))]
+    /// The GID mappings of the user namespace, can be specified multiple times
+    gid_map: Vec<String>,
 
-        for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
-            is_dir_or_create(&self.parent_dir_for_namespace(ns.name))?;
-        }
+    #[get = "pub"]
+    #[clap(subcommand)]
+    #[serde(skip)]
+    /// The subcommand to be run instead of pinning, if any
+    subcommand: Option<SubCommand>,
 
-        debug!("CLI provided config is valid");
-        Ok(())
-    }
-    pub fn parent_dir_for_namespace(&self, name: &str) -> PathBuf {
-        return self.dir().join(format!("{}ns", name));
-    }
-}
+    #[get = "pub"]
+    #[clap(skip)]
+    #[serde(serialize_with = "serialize_namespaces")]
+    /// The namespaces resolved during validation
+    namespaces: Namespaces,
 
-fn is_dir_or_create(path: &PathBuf) -> Result<()> {
-    if !path.exists() {
-        create_dir(path.display().to_string())?
-    }
-    if !metadata(path.clone())?.is_dir() {
-        bail!("given path {} is not a directory", path.display())
-    }
-    Ok(())
-}
+    #[get = "pub"]
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The UID mappings parsed during validation
+    uid_mappings: Vec<IdMapping>,
 
+    #[get = "pub"]
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The GID mappings parsed during validation
+    gid_mappings: Vec<IdMapping>,
 
-impl Default for Config {
-    fn default() -> Self {
-        Self::parse()
-    }
+    #[get = "pub"]
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The directories which have been created during validation
+    created_dirs: Vec<PathBuf>,
+
+    #[clap(skip)]
+    #[serde(skip)]
+    /// The matches of the command line arguments the configuration has been
+    /// parsed from, which is the only case the `PINNS_*` environment
+    /// variables and the config file apply to
+    matches: Option<ArgMatches>,
 }
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use tempfile::NamedTempFile;
-    use std::fs::File;
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+/// The configuration as it can be provided via a file
+///
+/// It covers all options of `Config` by their field names, except for bound
+/// file descriptors and the ones triggering one-off actions like printing the
+/// configuration.
+struct FileConfig {
+    #[schemars(with = "Option<String>")]
+    /// The logging level, one of `trace`, `debug`, `info`, `warn`, `error` or
+    /// `off`
+    log_level: Option<LevelFilter>,
 
-    #[test]
-    fn validate_success() -> Result<()> {
-        let mut c = Config::default();
-        c.cgroup = true;
-        c.validate()
-    }
+    /// Suppress all log output
+    quiet: Option<bool>,
 
-    #[test]
-    fn validate_failed_no_namespaces() {
-        let mut c = Config::default();
-        assert!(c.validate().is_err())
-    }
+    /// Disable colored log output
+    no_color: Option<bool>,
 
-    #[test]
-    fn validate_failed_not_existing_path() {
-        let mut c = Config::default();
-        c.dir = PathBuf::from("/not/existing/path");
-        assert!(c.validate().is_err())
-    }
+    /// The format of the log messages
+    log_format: Option<LogFormat>,
 
-    #[test]
-    fn validate_failed_path_not_dir() -> Result<()> {
-        let mut c = Config::default();
-        c.dir = NamedTempFile::new()?.path().into();
-        assert!(c.validate().is_err());
-        Ok(())
-    }
+    /// Append the log messages to the file instead of writing them to stderr
+    log_file: Option<PathBuf>,
 
-    #[test]
-    fn validate_failed_parent_dir_file() -> Result<()> {
-        let mut c = Config::default();
-        c.uts = true;
-        let _ = File::create(c.parent_dir_for_namespace("uts").display().to_string());
-        assert!(c.validate().is_err());
+    /// The parent directory for the pinned namespaces
+    dir: Option<PathBuf>,
+
+    /// The file name each namespace will be pinned to
+    filename: Option<String>,
+
+    /// The file name the cgroup namespace will be pinned to
+    cgroup_filename: Option<String>,
+
+    /// The file name the IPC namespace will be pinned to
+    ipc_filename: Option<String>,
+
+    /// The file name the mount namespace will be pinned to
+    mnt_filename: Option<String>,
+
+    /// The file name the network namespace will be pinned to
+    net_filename: Option<String>,
+
+    /// The file name the PID namespace will be pinned to
+    pid_filename: Option<String>,
+
+    /// The file name the time namespace will be pinned to
+    time_filename: Option<String>,
+
+    /// The file name the UTS namespace will be pinned to
+    uts_filename: Option<String>,
+
+    /// The file name the user namespace will be pinned to
+    user_filename: Option<String>,
+
+    /// The parent directory for the cgroup namespace
+    cgroup_dir: Option<PathBuf>,
+
+    /// The parent directory for the IPC namespace
+    ipc_dir: Option<PathBuf>,
+
+    /// The parent directory for the mount namespace
+    mnt_dir: Option<PathBuf>,
+
+    /// The parent directory for the network namespace
+    net_dir: Option<PathBuf>,
+
+    /// The parent directory for the PID namespace
+    pid_dir: Option<PathBuf>,
+
+    /// The parent directory for the time namespace
+    time_dir: Option<PathBuf>,
+
+    /// The parent directory for the UTS namespace
+    uts_dir: Option<PathBuf>,
+
+    /// The parent directory for the user namespace
+    user_dir: Option<PathBuf>,
+
+    /// An existing cgroup namespace file to be pinned
+    cgroup_source: Option<PathBuf>,
+
+    /// An existing IPC namespace file to be pinned
+    ipc_source: Option<PathBuf>,
+
+    /// An existing mount namespace file to be pinned
+    mnt_source: Option<PathBuf>,
+
+    /// An existing network namespace file to be pinned
+    net_source: Option<PathBuf>,
+
+    /// An existing PID namespace file to be pinned
+    pid_source: Option<PathBuf>,
+
+    /// An existing time namespace file to be pinned
+    time_source: Option<PathBuf>,
+
+    /// An existing UTS namespace file to be pinned
+    uts_source: Option<PathBuf>,
+
+    /// An existing user namespace file to be pinned
+    user_source: Option<PathBuf>,
+
+    /// Pin every namespace without a file name override to a random file name
+    unique_per_namespace: Option<bool>,
+
+    /// Pin the namespaces directly into `dir`
+    flat: Option<bool>,
+
+    /// The suffix of the per namespace subdirectories
+    dir_suffix: Option<String>,
+
+    /// Pin all namespaces supported by the running kernel
+    all: Option<bool>,
+
+    /// Pin the cgroup namespace
+    cgroup: Option<bool>,
+
+    /// Pin the IPC namespace
+    ipc: Option<bool>,
+
+    /// Pin the mount namespace
+    mnt: Option<bool>,
+
+    /// Pin the network namespace
+    net: Option<bool>,
+
+    /// Pin the PID namespace
+    pid: Option<bool>,
+
+    /// Pin the time namespace
+    time: Option<bool>,
+
+    /// Pin the UTS namespace
+    uts: Option<bool>,
+
+    /// Pin the user namespace
+    user: Option<bool>,
+
+    /// Pin the namespaces to be created by an OCI runtime spec
+    from_oci_spec: Option<PathBuf>,
+
+    #[serde(rename = "namespaces")]
+    /// The names of the namespaces to pin
+    namespace_list: Option<Vec<String>>,
+
+    /// The names of the namespaces not to pin
+    disable: Option<Vec<String>>,
+
+    /// The format of the pinned paths output
+    output_format: Option<OutputFormat>,
+
+    /// Include the inode number of every pinned namespace in the output
+    include_inode: Option<bool>,
+
+    #[schemars(with = "Option<String>")]
+    #[serde(deserialize_with = "deserialize_mode")]
+    /// The octal permissions of the created directories, like `0755`
+    dir_mode: Option<u32>,
+
+    #[schemars(with = "Option<String>")]
+    #[serde(deserialize_with = "deserialize_mode")]
+    /// The octal permissions of the created namespace files, like `0644`
+    file_mode: Option<u32>,
+
+    /// The owning user of the created namespace files
+    uid: Option<u32>,
+
+    /// The owning group of the created namespace files
+    gid: Option<u32>,
+
+    /// Create missing parent directories of `dir`
+    mkdir: Option<bool>,
+
+    /// Only unshare the namespaces without bind mounting them
+    no_pin: Option<bool>,
+
+    /// Replace already existing namespace files
+    force: Option<bool>,
+
+    /// Replace already existing namespace files if they are empty leftovers
+    overwrite_existing_empty: Option<bool>,
+
+    /// Join a fresh session keyring after unsharing the namespaces
+    new_keyring: Option<bool>,
+
+    /// Log the duration of the pinning steps
+    timing: Option<bool>,
+
+    /// Remount every pinned namespace file read-only
+    read_only: Option<bool>,
+
+    #[schemars(with = "Option<Vec<String>>")]
+    #[serde(deserialize_with = "deserialize_mount_flags")]
+    /// The flags to remount every pinned namespace file with, like `nosuid`
+    mount_flags: Option<Vec<MsFlags>>,
+
+    /// Verify that every bind mount shows up as namespace file system mount
+    verify: Option<bool>,
+
+    /// Refuse symlinks within the path of every pinned namespace file below
+    /// the parent directory
+    no_symlinks: Option<bool>,
+
+    /// Refuse to pin into a directory on a shared mount
+    strict: Option<bool>,
+
+    /// Fail upfront if requested features are not supported by the kernel
+    strict_kernel_check: Option<bool>,
+
+    /// Mount a dedicated tmpfs at the empty `dir` before pinning
+    mount_tmpfs: Option<bool>,
+
+    /// Print the namespaces to be unshared and bind mounted without doing it
+    dry_run: Option<bool>,
+
+    /// The mount point of the proc file system providing the namespaces
+    proc_path: Option<PathBuf>,
+
+    /// Atomically write the pinned namespace paths as JSON to the file
+    pin_paths_file: Option<PathBuf>,
+
+    /// Atomically write a JSON document describing the pins to the file
+    metadata_file: Option<PathBuf>,
+
+    /// Record the pinned namespaces in the index `dir`/pinns.json
+    index: Option<bool>,
+
+    /// Send the file descriptors of the namespaces to the unix socket
+    pass_fd_socket: Option<PathBuf>,
+
+    /// The number of retries if bind mounting fails with EBUSY or EAGAIN
+    mount_retries: Option<u32>,
+
+    /// The initial delay between mount retries in milliseconds
+    mount_retry_delay: Option<u64>,
+
+    /// The number of namespaces to bind concurrently
+    jobs: Option<usize>,
+
+    /// The mount propagation of the bind mounted namespaces
+    propagation: Option<Propagation>,
+
+    /// The SELinux context to be applied to the pinned namespace files
+    selinux_label: Option<String>,
+
+    /// The hostname of the pinned UTS namespace
+    uts_hostname: Option<String>,
+
+    /// The offset of the monotonic clock within the pinned time namespace
+    time_offset_monotonic: Option<i64>,
+
+    /// The offset of the boottime clock within the pinned time namespace
+    time_offset_boottime: Option<i64>,
+
+    /// Fail if the pinned cgroup namespace does not use cgroup v2
+    require_cgroup_v2: Option<bool>,
+
+    /// The cgroup v2 directory the process moves into before unsharing
+    cgroup_path: Option<PathBuf>,
+
+    /// Join the selected namespaces of the process instead of creating new ones
+    join_pid: Option<i32>,
+
+    /// Pin the selected namespaces of the process without creating new ones
+    pin_pid: Option<i32>,
+
+    /// Skip the namespaces of the process which are the same as the own ones
+    skip_unchanged: Option<bool>,
+
+    /// The UID mappings of the user namespace as `container:host:size`
+    uid_map: Option<Vec<String>>,
+
+    /// The GID mappings of the user namespace as `container:host:size`
+    gid_map: Option<Vec<String>>,
+}
+
+#[derive(Clap, Clone)]
+pub enum SubCommand {
+    #[clap(name = "completions", setting(AppSettings::Hidden))]
+    /// Print the completion script for a shell
+    Completions(Completions),
+
+    #[clap(name = "config-schema")]
+    /// Print the JSON schema of the configuration file
+    ConfigSchema(ConfigSchema),
+
+    #[clap(name = "exec")]
+    /// Pin the namespaces and execute a command within them
+    Exec(Exec),
+
+    #[clap(name = "gc")]
+    /// Remove stale namespace files and empty directories left behind, for
+    /// example by a reboot
+    Gc(Gc),
+
+    #[clap(name = "list")]
+    /// List the pinned namespaces and whether they are still mounted
+    List(List),
+
+    #[clap(name = "selftest")]
+    /// Pin and unpin a throwaway UTS namespace to verify that pinning works
+    Selftest(Selftest),
+
+    #[clap(name = "serve")]
+    /// Listen on a unix socket and pin namespaces on request
+    Serve(Serve),
+
+    #[clap(name = "unpin")]
+    /// Unmount and remove previously pinned namespaces
+    Unpin(Unpin),
+}
+
+impl SubCommand {
+    /// Validate the preconditions of the subcommand
+    ///
+    /// The exec subcommand pins the namespaces as well, which is validated
+    /// by the configuration itself.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            SubCommand::Completions(_) | SubCommand::ConfigSchema(_) => Ok(()),
+            SubCommand::Exec(exec) => exec.validate(),
+            SubCommand::Gc(gc) => gc.validate(),
+            SubCommand::List(list) => list.validate(),
+            SubCommand::Selftest(selftest) => is_existing_dir(selftest.dir()),
+            SubCommand::Serve(serve) => serve.validate(),
+            SubCommand::Unpin(unpin) => unpin.validate(),
+        }
+    }
+}
+
+#[derive(Clap, Clone, CopyGetters)]
+pub struct Completions {
+    #[get_copy = "pub"]
+    #[clap(
+        possible_values(&["bash", "elvish", "fish", "powershell", "zsh"]),
+        value_name("SHELL")
+    )]
+    /// The shell to print the completion script for
+    shell: Shell,
+}
+
+#[derive(Clap, Clone)]
+pub struct ConfigSchema {}
+
+#[derive(Clap, Clone, Getters, CopyGetters)]
+pub struct Serve {
+    #[get = "pub"]
+    #[clap(long("socket"), value_name("PATH"))]
+    /// The path of the unix socket to listen at, which must not exist yet
+    socket: PathBuf,
+
+    #[get = "pub"]
+    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    /// The parent directory for the pinned namespaces of requests without one
+    dir: PathBuf,
+
+    #[get_copy = "pub"]
+    #[clap(default_value("0"), long("idle-timeout"), value_name("SECONDS"))]
+    /// Exit after receiving no requests for the duration, zero to never exit
+    idle_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0600"),
+        long("socket-mode"),
+        parse(try_from_str = parse_mode),
+        value_name("MODE")
+    )]
+    /// The octal permissions of the socket, which decide who may request pins
+    socket_mode: u32,
+}
+
+impl Serve {
+    /// Validate the socket path and the default parent directory
+    pub fn validate(&self) -> Result<()> {
+        if self.socket.symlink_metadata().is_ok() {
+            bail!("socket path {} exists already", self.socket.display())
+        }
+        is_dir_if_existing(&self.dir)
+    }
+}
+
+#[derive(Clap, Clone, Getters)]
+pub struct Selftest {
+    #[get = "pub"]
+    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    /// The directory in which the temporary namespace gets pinned
+    dir: PathBuf,
+}
+
+#[derive(Clap, Clone, Getters, CopyGetters)]
+pub struct Exec {
+    #[get = "pub"]
+    #[clap(required(true), value_name("COMMAND"))]
+    /// The command and its arguments to be executed
+    command: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(long("pid-file"), value_name("PATH"))]
+    /// Atomically write the PID of the command to the file while it runs
+    pid_file: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(long("wait"))]
+    /// Keep the namespaces pinned after the command exited until SIGINT or
+    /// SIGTERM is received, then unpin them. Together with `--no-pin` nothing
+    /// gets unpinned, but the namespaces stay alive as long as pinns waits.
+    wait: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        long("oom-score-adj"),
+        parse(try_from_str = parse_oom_score_adj),
+        value_name("SCORE")
+    )]
+    /// Adjust the OOM score of the command, between -1000 and 1000
+    oom_score_adj: Option<i32>,
+
+    #[get = "pub"]
+    #[clap(
+        long("cap-drop"),
+        parse(try_from_str = parse_capability),
+        use_delimiter(true),
+        value_name("CAPABILITIES")
+    )]
+    /// A comma separated list of capabilities to drop before executing the
+    /// command, like `CAP_NET_RAW,sys_admin`
+    cap_drop: Vec<u32>,
+}
+
+impl Exec {
+    /// Validate that the PID file can be written
+    pub fn validate(&self) -> Result<()> {
+        let parent = self.pid_file.as_deref().and_then(Path::parent);
+        match parent {
+            Some(dir) if !dir.as_os_str().is_empty() => is_existing_dir(dir),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clap, Clone, Getters)]
+pub struct Gc {
+    #[get = "pub"]
+    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    /// The parent directory of the pinned namespaces
+    dir: PathBuf,
+
+    #[get = "pub"]
+    #[clap(default_value("ns"), long("dir-suffix"), value_name("SUFFIX"))]
+    /// The suffix of the per namespace subdirectories, like `ns` for `netns`
+    dir_suffix: String,
+
+    #[get_copy = "pub"]
+    #[clap(long("keep-dirs"))]
+    /// Keep the per namespace subdirectories for reuse, even if empty
+    keep_dirs: bool,
+}
+
+impl Gc {
+    /// Validate the parent directory and the subdirectory suffix
+    pub fn validate(&self) -> Result<()> {
+        is_existing_dir(&self.dir)?;
+        validate_dir_suffix(&self.dir_suffix)
+    }
+}
+
+#[derive(Clap, Clone, Getters)]
+pub struct List {
+    #[get = "pub"]
+    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    /// The parent directory of the pinned namespaces
+    dir: PathBuf,
+
+    #[get = "pub"]
+    #[clap(default_value("ns"), long("dir-suffix"), value_name("SUFFIX"))]
+    /// The suffix of the per namespace subdirectories, like `ns` for `netns`
+    dir_suffix: String,
+}
+
+impl List {
+    /// Validate the parent directory and the subdirectory suffix
+    pub fn validate(&self) -> Result<()> {
+        is_existing_dir(&self.dir)?;
+        validate_dir_suffix(&self.dir_suffix)
+    }
+}
+
+#[derive(Clap, Clone, Getters, CopyGetters)]
+pub struct Unpin {
+    #[get = "pub"]
+    #[clap(default_value(&TEMP_DIR), long("dir"), short("d"), value_name("DIRECTORY"))]
+    /// The parent directory of the pinned namespaces
+    dir: PathBuf,
+
+    #[get = "pub"]
+    #[clap(default_value("ns"), long("dir-suffix"), value_name("SUFFIX"))]
+    /// The suffix of the per namespace subdirectories, like `ns` for `netns`
+    dir_suffix: String,
+
+    #[get = "pub"]
+    #[clap(long("filename"), short("f"), value_name("FILENAME"))]
+    /// The file name the namespaces have been pinned to
+    filename: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        possible_values(NAMESPACE_NAMES),
+        required_unless("metadata-file"),
+        requires("filename"),
+        value_name("NAMESPACE")
+    )]
+    /// The namespaces to be unpinned
+    namespaces: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(long("metadata-file"), value_name("PATH"))]
+    /// Unpin all namespaces recorded in the metadata file written by
+    /// `--metadata-file` and remove it afterwards
+    metadata_file: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(long("mount-tmpfs"))]
+    /// Unmount the tmpfs at `dir` created by `--mount-tmpfs` if nothing is
+    /// pinned there anymore
+    mount_tmpfs: bool,
+
+    #[get_copy = "pub"]
+    #[clap(long("keep-dirs"))]
+    /// Keep the per namespace subdirectories for reuse, instead of removing
+    /// them once empty
+    keep_dirs: bool,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("true"),
+        long("detach"),
+        parse(try_from_str),
+        possible_values(&["true", "false"]),
+        value_name("BOOL")
+    )]
+    /// Lazily detach the mounts, otherwise unmounting fails if they are busy
+    detach: bool,
+}
+
+impl Unpin {
+    /// Validate the file name the namespaces have been pinned to and the
+    /// subdirectory suffix
+    pub fn validate(&self) -> Result<()> {
+        if let Some(filename) = &self.filename {
+            validate_filename(filename)?;
+        }
+        validate_dir_suffix(&self.dir_suffix)
+    }
+
+    /// The path where the namespace has been pinned to
+    pub fn bind_path_for_namespace(&self, name: &str) -> PathBuf {
+        // The file name is required by the parser if namespaces are provided
+        let filename = self.filename.as_deref().unwrap_or_default();
+        parent_dir_for_namespace(self.dir(), name, &self.dir_suffix).join(filename)
+    }
+
+    /// The flags used for unmounting the namespaces
+    pub fn umount_flags(&self) -> MntFlags {
+        if self.detach {
+            MntFlags::MNT_DETACH
+        } else {
+            MntFlags::empty()
+        }
+    }
+}
+
+#[derive(Clone, Getters)]
+pub struct Namespaces {
+    #[get = "pub"]
+    cgroup: Namespace,
+
+    #[get = "pub"]
+    ipc: Namespace,
+
+    #[get = "pub"]
+    mnt: Namespace,
+
+    #[get = "pub"]
+    net: Namespace,
+
+    #[get = "pub"]
+    pid: Namespace,
+
+    #[get = "pub"]
+    time: Namespace,
+
+    #[get = "pub"]
+    uts: Namespace,
+
+    #[get = "pub"]
+    user: Namespace,
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        // All names are known, which means that the lookup cannot fail
+        let namespace = |name| Namespace::from_name(name).expect("unknown namespace");
+        Namespaces {
+            cgroup: namespace("cgroup"),
+            ipc: namespace("ipc"),
+            mnt: namespace("mnt"),
+            net: namespace("net"),
+            pid: namespace("pid"),
+            time: namespace("time"),
+            uts: namespace("uts"),
+            user: namespace("user"),
+        }
+    }
+}
+
+impl Namespaces {
+    /// Retrieve the mutable namespace of the provided name
+    fn get_mut(&mut self, name: &str) -> Option<&mut Namespace> {
+        match name {
+            "cgroup" => Some(&mut self.cgroup),
+            "ipc" => Some(&mut self.ipc),
+            "mnt" => Some(&mut self.mnt),
+            "net" => Some(&mut self.net),
+            "pid" => Some(&mut self.pid),
+            "time" => Some(&mut self.time),
+            "uts" => Some(&mut self.uts),
+            "user" => Some(&mut self.user),
+            _ => None,
+        }
+    }
+}
+
+impl IntoIterator for &Namespaces {
+    type Item = Namespace;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        vec![
+            self.user, self.cgroup, self.ipc, self.mnt, self.net, self.pid, self.time, self.uts,
+        ]
+        .into_iter()
+    }
+}
+
+#[derive(Clone, Copy, Getters, CopyGetters)]
+pub struct Namespace {
+    #[get = "pub"]
+    name: &'static str,
+
+    #[get = "pub"]
+    /// The entry below `/proc/<pid>/ns` which refers to the new namespace
+    proc_name: &'static str,
+
+    #[get_copy = "pub"]
+    enabled: bool,
+
+    #[get_copy = "pub"]
+    clone_flag: CloneFlags,
+}
+
+impl Namespace {
+    /// Create a disabled namespace from its name, like `net`
+    ///
+    /// This is the single mapping of the namespace names to their clone flags
+    /// and proc entries.
+    pub fn from_name(name: &str) -> Option<Namespace> {
+        let (name, proc_name, clone_flag) = match name {
+            "cgroup" => ("cgroup", "cgroup", CloneFlags::CLONE_NEWCGROUP),
+            "ipc" => ("ipc", "ipc", CloneFlags::CLONE_NEWIPC),
+            "mnt" => ("mnt", "mnt", CloneFlags::CLONE_NEWNS),
+            "net" => ("net", "net", CloneFlags::CLONE_NEWNET),
+            // The calling process does not enter the new PID and time
+            // namespaces, only its children do, where the first child becomes
+            // PID 1 of the PID namespace. The time flag is not yet known to nix.
+            "pid" => ("pid", "pid_for_children", CloneFlags::CLONE_NEWPID),
+            "time" => ("time", "time_for_children", unsafe {
+                CloneFlags::from_bits_unchecked(CLONE_NEWTIME)
+            }),
+            "uts" => ("uts", "uts", CloneFlags::CLONE_NEWUTS),
+            "user" => ("user", "user", CloneFlags::CLONE_NEWUSER),
+            _ => return None,
+        };
+        Some(Namespace {
+            name,
+            proc_name,
+            enabled: false,
+            clone_flag,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CopyGetters)]
+/// A single UID or GID mapping of a user namespace
+pub struct IdMapping {
+    #[get_copy = "pub"]
+    container_id: u32,
+
+    #[get_copy = "pub"]
+    host_id: u32,
+
+    #[get_copy = "pub"]
+    size: u32,
+}
+
+impl FromStr for IdMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let ids = s
+            .split(':')
+            .map(|x| x.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .context(format!("ID mapping {} contains an invalid number", s))?;
+
+        match ids.as_slice() {
+            [container_id, host_id, size] if *size > 0 => Ok(IdMapping {
+                container_id: *container_id,
+                host_id: *host_id,
+                size: *size,
+            }),
+            _ => Err(format_err!(
+                "ID mapping {} is not in the format container:host:size",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IdMapping {
+    /// Format the mapping as expected by `/proc/<pid>/[ug]id_map`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.container_id, self.host_id, self.size)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// The available log formats
+pub enum LogFormat {
+    /// Plain human readable log lines
+    Text,
+
+    /// A JSON object per log line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// The mount propagation types of the bind mounts
+pub enum Propagation {
+    /// Do not propagate mount events
+    Private,
+
+    /// Propagate mount events between the peers
+    Shared,
+
+    /// Receive mount events from the master only
+    Slave,
+
+    /// Keep the propagation as inherited from the parent mount
+    Unchanged,
+}
+
+impl Propagation {
+    /// The mount flag to be used for changing the propagation, if required
+    pub fn flag(self) -> Option<MsFlags> {
+        match self {
+            Propagation::Private => Some(MsFlags::MS_PRIVATE),
+            Propagation::Shared => Some(MsFlags::MS_SHARED),
+            Propagation::Slave => Some(MsFlags::MS_SLAVE),
+            Propagation::Unchanged => None,
+        }
+    }
+}
+
+impl FromStr for Propagation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "private" => Ok(Propagation::Private),
+            "shared" => Ok(Propagation::Shared),
+            "slave" => Ok(Propagation::Slave),
+            "unchanged" => Ok(Propagation::Unchanged),
+            _ => bail!("unknown mount propagation {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The shells completion scripts can be generated for
+pub enum Shell {
+    /// The Bourne Again shell
+    Bash,
+
+    /// The Elvish shell
+    Elvish,
+
+    /// The friendly interactive shell
+    Fish,
+
+    /// The PowerShell
+    PowerShell,
+
+    /// The Z shell
+    Zsh,
+}
+
+impl Shell {
+    /// Write the completion script of the command line interface to the buffer
+    pub fn generate(self, buf: &mut dyn Write) {
+        let mut app = Config::app(false);
+        match self {
+            Shell::Bash => generate::<Bash, _>(&mut app, "pinns", buf),
+            Shell::Elvish => generate::<Elvish, _>(&mut app, "pinns", buf),
+            Shell::Fish => generate::<Fish, _>(&mut app, "pinns", buf),
+            Shell::PowerShell => generate::<PowerShell, _>(&mut app, "pinns", buf),
+            Shell::Zsh => generate::<Zsh, _>(&mut app, "pinns", buf),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "elvish" => Ok(Shell::Elvish),
+            "fish" => Ok(Shell::Fish),
+            "powershell" => Ok(Shell::PowerShell),
+            "zsh" => Ok(Shell::Zsh),
+            _ => bail!("unknown shell {}", s),
+        }
+    }
+}
+
+impl Config {
+    /// Parse the command line arguments of the process
+    pub fn parse_args() -> Self {
+        Self::parse_args_from(args_os())
+    }
+
+    /// Parse the provided command line arguments
+    ///
+    /// The help is colored unless disabled via `--no-color` or `NO_COLOR`,
+    /// which has to be decided before parsing because parsing prints the help.
+    pub fn parse_args_from<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let color = !no_color_env() && !args.iter().any(|x| x == "--no-color");
+        let matches = Self::app(color).get_matches_from(args);
+        let mut config = Self::from_arg_matches(&matches);
+        config.matches = Some(matches);
+        config
+    }
+
+    /// The command line interface, optionally with colored help
+    pub fn app<'a>(color: bool) -> App<'a> {
+        let app = Self::into_app();
+        if color {
+            app.global_setting(AppSettings::ColoredHelp)
+        } else {
+            app
+        }
+    }
+
+    /// Create a builder for a configuration which does not rely on command
+    /// line arguments
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Enable the flags whose `PINNS_*` environment variable is set
+    ///
+    /// Arguments taking a value are read from their environment variable by
+    /// clap already, which does not work for flags without turning them into
+    /// arguments taking a value as well. Configurations which have not been
+    /// parsed via `parse_args`, like the ones of the builder, are left as is.
+    pub fn merge_env(&mut self) -> Result<()> {
+        if self.matches.is_none() {
+            return Ok(());
+        }
+        let flags = vec![
+            ("PINNS_QUIET", &mut self.quiet),
+            ("PINNS_NO_COLOR", &mut self.no_color),
+            ("PINNS_UNIQUE_PER_NAMESPACE", &mut self.unique_per_namespace),
+            ("PINNS_FLAT", &mut self.flat),
+            ("PINNS_ALL", &mut self.all),
+            ("PINNS_CGROUP", &mut self.cgroup),
+            ("PINNS_IPC", &mut self.ipc),
+            ("PINNS_MOUNT", &mut self.mnt),
+            ("PINNS_NET", &mut self.net),
+            ("PINNS_PID", &mut self.pid),
+            ("PINNS_TIME", &mut self.time),
+            ("PINNS_UTS", &mut self.uts),
+            ("PINNS_USER", &mut self.user),
+            ("PINNS_FROM_STDIN", &mut self.from_stdin),
+            ("PINNS_MKDIR", &mut self.mkdir),
+            ("PINNS_NO_PIN", &mut self.no_pin),
+            ("PINNS_FORCE", &mut self.force),
+            ("PINNS_INCLUDE_INODE", &mut self.include_inode),
+            ("PINNS_OVERWRITE_EXISTING_EMPTY", &mut self.overwrite_existing_empty),
+            ("PINNS_NEW_KEYRING", &mut self.new_keyring),
+            ("PINNS_TIMING", &mut self.timing),
+            ("PINNS_READ_ONLY", &mut self.read_only),
+            ("PINNS_VERIFY", &mut self.verify),
+            ("PINNS_NO_SYMLINKS", &mut self.no_symlinks),
+            ("PINNS_MOUNT_TMPFS", &mut self.mount_tmpfs),
+            ("PINNS_DRY_RUN", &mut self.dry_run),
+            ("PINNS_INDEX", &mut self.index),
+            ("PINNS_PRINT_CONFIG", &mut self.print_config),
+            ("PINNS_STRICT", &mut self.strict),
+            ("PINNS_STRICT_KERNEL_CHECK", &mut self.strict_kernel_check),
+            ("PINNS_REQUIRE_CGROUP_V2", &mut self.require_cgroup_v2),
+            ("PINNS_SKIP_UNCHANGED", &mut self.skip_unchanged),
+        ];
+        for (name, flag) in flags {
+            let value = match var(name) {
+                Ok(value) => value,
+                Err(VarError::NotPresent) => continue,
+                Err(e) => return Err(e).context(format!("unable to read {}", name)),
+            };
+            // Flags enabled on the command line stay enabled
+            *flag |= parse_flag(&value).context(format!("invalid value of {}", name))?;
+        }
+        Ok(())
+    }
+
+    /// Merge the configuration file into the command line arguments, if provided
+    ///
+    /// Options set on the command line or via their environment variable take
+    /// precedence over the file. Namespaces selected that way replace the ones
+    /// selected by the file as a whole, so that they can be narrowed down.
+    pub fn merge_config_file(&mut self) -> Result<()> {
+        let path = match self.config_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        debug!("loading config file {}", path.display());
+        let file =
+            File::open(path).context(format!("unable to open config file {}", path.display()))?;
+        let c: FileConfig = serde_json::from_reader(file)
+            .context(format!("unable to parse config file {}", path.display()))?;
+
+        let namespaces_selected = NAMESPACE_SELECTION
+            .iter()
+            .any(|(name, env)| self.is_explicit(name, env));
+        if !namespaces_selected {
+            merge_file_options!(
+                self,
+                c,
+                all => "PINNS_ALL",
+                cgroup => "PINNS_CGROUP",
+                ipc => "PINNS_IPC",
+                mnt => "PINNS_MOUNT",
+                net => "PINNS_NET",
+                pid => "PINNS_PID",
+                time => "PINNS_TIME",
+                uts => "PINNS_UTS",
+                user => "PINNS_USER",
+                namespace_list => "PINNS_NAMESPACES",
+            );
+            merge_file_options!(
+                self,
+                c,
+                Some:
+                from_oci_spec => "PINNS_FROM_OCI_SPEC",
+            );
+        }
+        merge_file_options!(
+            self,
+            c,
+            log_level => "PINNS_LOG_LEVEL",
+            quiet => "PINNS_QUIET",
+            no_color => "PINNS_NO_COLOR",
+            log_format => "PINNS_LOG_FORMAT",
+            dir => "PINNS_DIR",
+            filename => "PINNS_FILENAME",
+            unique_per_namespace => "PINNS_UNIQUE_PER_NAMESPACE",
+            flat => "PINNS_FLAT",
+            dir_suffix => "PINNS_DIR_SUFFIX",
+            disable => "PINNS_DISABLE",
+            output_format => "PINNS_OUTPUT_FORMAT",
+            include_inode => "PINNS_INCLUDE_INODE",
+            dir_mode => "PINNS_DIR_MODE",
+            file_mode => "PINNS_FILE_MODE",
+            mkdir => "PINNS_MKDIR",
+            no_pin => "PINNS_NO_PIN",
+            force => "PINNS_FORCE",
+            overwrite_existing_empty => "PINNS_OVERWRITE_EXISTING_EMPTY",
+            new_keyring => "PINNS_NEW_KEYRING",
+            timing => "PINNS_TIMING",
+            read_only => "PINNS_READ_ONLY",
+            mount_flags => "PINNS_MOUNT_FLAGS",
+            verify => "PINNS_VERIFY",
+            no_symlinks => "PINNS_NO_SYMLINKS",
+            strict => "PINNS_STRICT",
+            strict_kernel_check => "PINNS_STRICT_KERNEL_CHECK",
+            mount_tmpfs => "PINNS_MOUNT_TMPFS",
+            dry_run => "PINNS_DRY_RUN",
+            proc_path => "PINNS_PROC_PATH",
+            index => "PINNS_INDEX",
+            mount_retries => "PINNS_MOUNT_RETRIES",
+            mount_retry_delay => "PINNS_MOUNT_RETRY_DELAY",
+            jobs => "PINNS_JOBS",
+            propagation => "PINNS_PROPAGATION",
+            require_cgroup_v2 => "PINNS_REQUIRE_CGROUP_V2",
+            skip_unchanged => "PINNS_SKIP_UNCHANGED",
+            uid_map => "PINNS_UID_MAP",
+            gid_map => "PINNS_GID_MAP",
+        );
+        merge_file_options!(
+            self,
+            c,
+            Some:
+            log_file => "PINNS_LOG_FILE",
+            cgroup_filename => "PINNS_CGROUP_FILENAME",
+            ipc_filename => "PINNS_IPC_FILENAME",
+            mnt_filename => "PINNS_MOUNT_FILENAME",
+            net_filename => "PINNS_NET_FILENAME",
+            pid_filename => "PINNS_PID_FILENAME",
+            time_filename => "PINNS_TIME_FILENAME",
+            uts_filename => "PINNS_UTS_FILENAME",
+            user_filename => "PINNS_USER_FILENAME",
+            cgroup_dir => "PINNS_CGROUP_DIR",
+            ipc_dir => "PINNS_IPC_DIR",
+            mnt_dir => "PINNS_MOUNT_DIR",
+            net_dir => "PINNS_NET_DIR",
+            pid_dir => "PINNS_PID_DIR",
+            time_dir => "PINNS_TIME_DIR",
+            uts_dir => "PINNS_UTS_DIR",
+            user_dir => "PINNS_USER_DIR",
+            cgroup_source => "PINNS_CGROUP_SOURCE",
+            ipc_source => "PINNS_IPC_SOURCE",
+            mnt_source => "PINNS_MOUNT_SOURCE",
+            net_source => "PINNS_NET_SOURCE",
+            pid_source => "PINNS_PID_SOURCE",
+            time_source => "PINNS_TIME_SOURCE",
+            uts_source => "PINNS_UTS_SOURCE",
+            user_source => "PINNS_USER_SOURCE",
+            uid => "PINNS_UID",
+            gid => "PINNS_GID",
+            pin_paths_file => "PINNS_PIN_PATHS_FILE",
+            metadata_file => "PINNS_METADATA_FILE",
+            pass_fd_socket => "PINNS_PASS_FD_SOCKET",
+            selinux_label => "PINNS_SELINUX_LABEL",
+            uts_hostname => "PINNS_UTS_HOSTNAME",
+            time_offset_monotonic => "PINNS_TIME_OFFSET_MONOTONIC",
+            time_offset_boottime => "PINNS_TIME_OFFSET_BOOTTIME",
+            cgroup_path => "PINNS_CGROUP_PATH",
+            join_pid => "PINNS_JOIN_PID",
+            pin_pid => "PINNS_PIN_PID",
+        );
+        Ok(())
+    }
+
+    /// Whether the option of the provided argument name, like `log-level`, has
+    /// been set on the command line or via its environment variable
+    ///
+    /// Only configurations parsed from the command line have explicit options,
+    /// whereas the defaults of clap do not count as set.
+    fn is_explicit(&self, name: &str, env: &str) -> bool {
+        self.matches.as_ref().map_or(false, |x| {
+            x.occurrences_of(name) > 0 || var_os(env).is_some()
+        })
+    }
+
+    /// Validate the configuration in their parameters for pinning
+    ///
+    /// Only pinning, either directly or via the exec subcommand, requires
+    /// namespaces. The other subcommands validate their own preconditions.
+    pub fn validate(&mut self) -> Result<()> {
+        if self.from_stdin {
+            let input = read_stdin().context("unable to read namespaces from stdin")?;
+            self.enable_namespaces(&input)?;
+        }
+
+        if let Some(path) = &self.from_oci_spec {
+            let list = spec_namespaces(path)?.join(",");
+            self.enable_namespaces(&list)?;
+        }
+
+        let list = self.namespace_list.join(",");
+        self.enable_namespaces(&list)?;
+
+        if self.all {
+            self.cgroup |= namespace_supported(&self.proc_path, "cgroup");
+            self.ipc |= namespace_supported(&self.proc_path, "ipc");
+            self.mnt |= namespace_supported(&self.proc_path, "mnt");
+            self.net |= namespace_supported(&self.proc_path, "net");
+            self.pid |= namespace_supported(&self.proc_path, "pid");
+            self.time |= namespace_supported(&self.proc_path, "time");
+            self.uts |= namespace_supported(&self.proc_path, "uts");
+            self.user |= namespace_supported(&self.proc_path, "user");
+        }
+
+        // Namespaces may have been enabled by name already
+        self.namespaces.cgroup.enabled |= self.cgroup;
+        self.namespaces.ipc.enabled |= self.ipc;
+        self.namespaces.mnt.enabled |= self.mnt;
+        self.namespaces.net.enabled |= self.net;
+        self.namespaces.pid.enabled |= self.pid;
+        self.namespaces.time.enabled |= self.time;
+        self.namespaces.uts.enabled |= self.uts;
+        self.namespaces.user.enabled |= self.user;
+
+        for (name, fd) in self.bind_fd.clone() {
+            if self.source_for_namespace(&name).is_some() {
+                bail!("{} namespace source is already set, cannot bind file descriptor", name)
+            }
+            let source = self.bind_fd_source(&name, fd)?;
+            if let Some(x) = self.source_for_namespace_mut(&name) {
+                *x = Some(source);
+            }
+        }
+
+        for name in NAMESPACE_NAMES {
+            let source = match self.source_for_namespace(name) {
+                Some(source) => source,
+                None => continue,
+            };
+            validate_namespace_source(&self.proc_path, name, source)?;
+            if let Some(ns) = self.namespaces.get_mut(name) {
+                ns.enabled = true;
+            }
+        }
+
+        for name in &self.disable {
+            if let Some(ns) = self.namespaces.get_mut(name) {
+                ns.enabled = false;
+            }
+        }
+
+        if self.namespaces().into_iter().all(|x| !x.enabled()) {
+            bail!("no namespace specified for pinning")
+        }
+
+        if self.strict_kernel_check {
+            let unsupported = self.unsupported_kernel_features();
+            if !unsupported.is_empty() {
+                bail!(
+                    "requested features are not supported by the running kernel: {}",
+                    unsupported.join(", ")
+                )
+            }
+        }
+
+        for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
+            if !namespace_supported(&self.proc_path, ns.name) {
+                bail!(
+                    "{} namespace is not supported by the running kernel, {} does not exist",
+                    ns.name,
+                    kernel_namespace_path(&self.proc_path, ns.name).display()
+                )
+            }
+        }
+
+        if let Some(hostname) = self.uts_hostname() {
+            if !self.namespaces.uts.enabled {
+                bail!("setting the hostname requires the UTS namespace to be pinned")
+            }
+            if hostname.len() > HOST_NAME_MAX {
+                bail!(
+                    "hostname {} exceeds the maximum length of {} bytes",
+                    hostname,
+                    HOST_NAME_MAX
+                )
+            }
+        }
+
+        if let Some(label) = self.selinux_label() {
+            if label.split(':').count() < 3 || label.contains('\0') {
+                bail!(
+                    "invalid SELinux label {}, expected user:role:type[:range]",
+                    label
+                )
+            }
+        }
+
+        for offset in [self.time_offset_monotonic, self.time_offset_boottime].iter().flatten() {
+            if !self.namespaces.time.enabled {
+                bail!("setting time offsets requires the time namespace to be pinned")
+            }
+            if !(-KTIME_SEC_MAX..=KTIME_SEC_MAX).contains(offset) {
+                bail!(
+                    "time offset {} exceeds the maximum of {} seconds",
+                    offset,
+                    KTIME_SEC_MAX
+                )
+            }
+        }
+
+        if self.require_cgroup_v2 {
+            if !self.namespaces.cgroup.enabled {
+                bail!("requiring cgroup v2 needs the cgroup namespace to be pinned")
+            }
+            if !cgroup_v2_host() {
+                bail!("host does not use cgroup v2, {} does not exist", CGROUP_V2_CONTROLLERS)
+            }
+        }
+
+        if let Some(path) = &self.cgroup_path {
+            if !self.enters_namespace(self.namespaces().cgroup())
+                || self.join_pid.is_some()
+                || self.pin_pid.is_some()
+            {
+                bail!("moving into a cgroup requires a new cgroup namespace")
+            }
+            if !mountinfo::is_on_cgroup2_mount(path)? {
+                bail!("cgroup path {} is not below a cgroup v2 mount", path.display())
+            }
+            if !path.join("cgroup.procs").is_file() {
+                bail!("cgroup path {} does not contain cgroup.procs", path.display())
+            }
+        }
+
+        match (self.uid, self.gid) {
+            (Some(uid), Some(gid)) => {
+                // Only privileged processes can give files away
+                if !Uid::effective().is_root()
+                    && (uid != Uid::effective().as_raw() || gid != Gid::effective().as_raw())
+                {
+                    bail!(
+                        "changing the owner of the namespace files to {}:{} requires root",
+                        uid,
+                        gid
+                    )
+                }
+            }
+            (None, None) => {}
+            _ => bail!("the owning user and group have to be provided together"),
+        }
+
+        self.uid_mappings = parse_id_mappings(&self.uid_map).context("invalid UID mapping")?;
+        self.gid_mappings = parse_id_mappings(&self.gid_map).context("invalid GID mapping")?;
+        if !self.namespaces.user.enabled
+            && !(self.uid_mappings.is_empty() && self.gid_mappings.is_empty())
+        {
+            bail!("UID and GID mappings require the user namespace to be pinned")
+        }
+
+        if self.new_keyring && !self.enters_namespace(self.namespaces().user()) {
+            bail!("joining a new session keyring requires a new user namespace")
+        }
+
+        if self.uts_source.is_some() && self.uts_hostname.is_some()
+            || self.time_source.is_some()
+                && (self.time_offset_monotonic.is_some() || self.time_offset_boottime.is_some())
+            || self.user_source.is_some()
+                && !(self.uid_mappings.is_empty() && self.gid_mappings.is_empty())
+        {
+            bail!("namespaces pinned from a source file cannot be modified")
+        }
+
+        if !self.proc_path.join("self").join("ns").is_dir() {
+            bail!("proc path {} does not contain self/ns", self.proc_path.display())
+        }
+
+        self.resolve_filenames()?;
+        for filename in [
+            &self.cgroup_filename,
+            &self.ipc_filename,
+            &self.mnt_filename,
+            &self.net_filename,
+            &self.pid_filename,
+            &self.time_filename,
+            &self.uts_filename,
+            &self.user_filename,
+        ]
+        .iter()
+        .copied()
+        .flatten()
+        {
+            validate_filename(filename)?;
+        }
+        validate_dir_suffix(&self.dir_suffix)?;
+        if self.unique_per_namespace {
+            for filename in [
+                &mut self.cgroup_filename,
+                &mut self.ipc_filename,
+                &mut self.mnt_filename,
+                &mut self.net_filename,
+                &mut self.pid_filename,
+                &mut self.time_filename,
+                &mut self.uts_filename,
+                &mut self.user_filename,
+            ]
+            .iter_mut()
+            {
+                filename.get_or_insert_with(|| Uuid::new_v4().to_hyphenated().to_string());
+            }
+        }
+
+        if self.flat {
+            let mut filenames = HashSet::new();
+            for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
+                if !filenames.insert(self.filename_for_namespace(ns.name)) {
+                    bail!(
+                        "file name {} is used by multiple namespaces within the flat layout, \
+                         use --unique-per-namespace or per namespace file names instead",
+                        self.filename_for_namespace(ns.name)
+                    )
+                }
+            }
+        }
+
+        match (self.join_pid(), self.pin_pid()) {
+            (Some(_), Some(_)) => bail!("joining and pinning a process cannot be combined"),
+            (Some(pid), None) | (None, Some(pid)) => {
+                self.validate_target_pid(pid)?;
+                if self.skip_unchanged {
+                    self.disable_unchanged_namespaces(pid)?;
+                }
+            }
+            (None, None) if self.skip_unchanged => {
+                bail!("skipping unchanged namespaces requires joining or pinning a process")
+            }
+            (None, None) => {}
+        }
+
+        if self.no_pin {
+            if self.pin_pid.is_some() {
+                bail!("pinning the namespaces of a process requires bind mounts")
+            }
+        } else {
+            is_dir_if_existing(self.dir())?;
+            for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
+                // Only the global directory gets created without `mkdir`
+                let dir = self.dir_for_namespace(ns.name);
+                if !self.mkdir && dir != self.dir() && !dir.is_dir() {
+                    bail!(
+                        "directory {} for the {} namespace does not exist",
+                        dir.display(),
+                        ns.name
+                    )
+                }
+                is_dir_if_existing(&self.parent_dir_for_namespace(ns.name))?;
+            }
+        }
+
+        debug!("CLI provided config is valid");
+        Ok(())
+    }
+
+    /// Create the parent directory of the pinned namespaces
+    pub fn prepare_dir(&mut self) -> Result<()> {
+        self.create_dir_if_missing(self.dir().clone(), self.mkdir)
+            .context("use --mkdir to create missing parent directories")
+    }
+
+    /// Create the directories for the namespaces to be pinned
+    ///
+    /// Requires the configuration to be validated first.
+    pub fn prepare_dirs(&mut self) -> Result<()> {
+        if self.no_pin {
+            return Ok(());
+        }
+
+        self.prepare_dir()?;
+        for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
+            self.create_dir_if_missing(self.parent_dir_for_namespace(ns.name), true)?;
+        }
+        Ok(())
+    }
+
+    /// The directory the namespace will be pinned into
+    pub fn parent_dir_for_namespace(&self, name: &str) -> PathBuf {
+        let dir = self.dir_for_namespace(name);
+        if self.flat {
+            dir.to_path_buf()
+        } else {
+            parent_dir_for_namespace(dir, name, &self.dir_suffix)
+        }
+    }
+
+    /// The parent directory for the namespace, which is `dir` if not
+    /// overridden for the namespace
+    pub fn dir_for_namespace(&self, name: &str) -> &Path {
+        let dir = match name {
+            "cgroup" => self.cgroup_dir.as_ref(),
+            "ipc" => self.ipc_dir.as_ref(),
+            "mnt" => self.mnt_dir.as_ref(),
+            "net" => self.net_dir.as_ref(),
+            "pid" => self.pid_dir.as_ref(),
+            "time" => self.time_dir.as_ref(),
+            "uts" => self.uts_dir.as_ref(),
+            "user" => self.user_dir.as_ref(),
+            _ => None,
+        };
+        dir.unwrap_or(&self.dir)
+    }
+
+    /// Replace the template tokens of the file name and the per namespace
+    /// file names
+    ///
+    /// All file names share the same UUID and index, whereas the index is the
+    /// first one not resulting in an existing bind path of any namespace.
+    fn resolve_filenames(&mut self) -> Result<()> {
+        let uuid = Uuid::new_v4().to_hyphenated().to_string();
+        for filename in self.filenames_mut() {
+            *filename = filename.replace("{uuid}", &uuid);
+        }
+
+        if self.filenames_mut().iter().any(|x| x.contains("{index}")) {
+            let mut index = 0;
+            while self.namespaces().into_iter().filter(|x| x.enabled()).any(|x| {
+                let filename = self.filename_for_namespace(x.name);
+                let candidate = filename.replace("{index}", &index.to_string());
+                self.parent_dir_for_namespace(x.name).join(candidate).exists()
+            }) {
+                index += 1;
+            }
+            for filename in self.filenames_mut() {
+                *filename = filename.replace("{index}", &index.to_string());
+            }
+        }
+
+        validate_filename(&self.filename)
+    }
+
+    /// The file name and all provided per namespace file names
+    fn filenames_mut(&mut self) -> Vec<&mut String> {
+        let mut filenames = vec![&mut self.filename];
+        filenames.extend(
+            vec![
+                &mut self.cgroup_filename,
+                &mut self.ipc_filename,
+                &mut self.mnt_filename,
+                &mut self.net_filename,
+                &mut self.pid_filename,
+                &mut self.time_filename,
+                &mut self.uts_filename,
+                &mut self.user_filename,
+            ]
+            .into_iter()
+            .filter_map(Option::as_mut),
+        );
+        filenames
+    }
+
+    /// The configuration of a request to the daemon, which keeps all options
+    /// of the daemon but the directory, the file name and the namespaces
+    ///
+    /// Requests without a file name get a random one each, unless the daemon
+    /// has been configured with one.
+    pub fn for_request(
+        &self,
+        dir: PathBuf,
+        filename: Option<String>,
+        namespaces: &[String],
+    ) -> Result<Self> {
+        let mut config = self.clone();
+        config.subcommand = None;
+        config.dir = dir;
+        config.filename = match filename {
+            Some(filename) => filename,
+            // The random default of the daemon would be shared by all requests
+            None if self.filename == *TEMP_FILE => "{uuid}".into(),
+            None => self.filename.clone(),
+        };
+        config.all = false;
+        config.cgroup = false;
+        config.ipc = false;
+        config.mnt = false;
+        config.net = false;
+        config.pid = false;
+        config.time = false;
+        config.uts = false;
+        config.user = false;
+        config.from_stdin = false;
+        config.from_oci_spec = None;
+        config.namespace_list = vec![];
+        config.namespaces = Namespaces::default();
+        config.enable_namespaces(&namespaces.join(","))?;
+        Ok(config)
+    }
+
+    /// Enable the namespaces of a newline or comma separated list of names
+    fn enable_namespaces(&mut self, list: &str) -> Result<()> {
+        for name in list.split(|c| c == '\n' || c == ',').map(str::trim) {
+            if name.is_empty() {
+                continue;
+            }
+            match self.namespaces.get_mut(name) {
+                Some(namespace) => namespace.enabled = true,
+                None => bail!(
+                    "unknown namespace {}, valid namespaces are: {}",
+                    name,
+                    NAMESPACE_NAMES.join(", ")
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the output may be colored, which is not the case if disabled
+    /// via `--no-color` or the `NO_COLOR` environment variable
+    pub fn color(&self) -> bool {
+        !self.no_color && !no_color_env()
+    }
+
+    /// The flags to remount the pinned namespace files with, including
+    /// `MS_RDONLY` for `--read-only`
+    pub fn remount_flags(&self) -> MsFlags {
+        let flags = self.mount_flags.iter().fold(MsFlags::empty(), |acc, x| acc | *x);
+        if self.read_only {
+            flags | MsFlags::MS_RDONLY
+        } else {
+            flags
+        }
+    }
+
+    /// The effective logging level, which is turned off in quiet mode
+    pub fn log_level(&self) -> LevelFilter {
+        if self.quiet {
+            LevelFilter::Off
+        } else {
+            self.log_level
+        }
+    }
+
+    /// The path the namespace will be pinned to
+    pub fn bind_path_for_namespace(&self, name: &str) -> PathBuf {
+        self.parent_dir_for_namespace(name).join(self.filename_for_namespace(name))
+    }
+
+    /// The file name the namespace will be pinned to
+    pub fn filename_for_namespace(&self, name: &str) -> &str {
+        let filename = match name {
+            "cgroup" => self.cgroup_filename.as_ref(),
+            "ipc" => self.ipc_filename.as_ref(),
+            "mnt" => self.mnt_filename.as_ref(),
+            "net" => self.net_filename.as_ref(),
+            "pid" => self.pid_filename.as_ref(),
+            "time" => self.time_filename.as_ref(),
+            "uts" => self.uts_filename.as_ref(),
+            "user" => self.user_filename.as_ref(),
+            _ => None,
+        };
+        filename.unwrap_or(&self.filename)
+    }
+
+    /// The existing namespace file to be pinned instead of the unshared or
+    /// joined namespace
+    pub fn source_for_namespace(&self, name: &str) -> Option<&PathBuf> {
+        match name {
+            "cgroup" => self.cgroup_source.as_ref(),
+            "ipc" => self.ipc_source.as_ref(),
+            "mnt" => self.mnt_source.as_ref(),
+            "net" => self.net_source.as_ref(),
+            "pid" => self.pid_source.as_ref(),
+            "time" => self.time_source.as_ref(),
+            "uts" => self.uts_source.as_ref(),
+            "user" => self.user_source.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The mutable source override of the namespace
+    fn source_for_namespace_mut(&mut self, name: &str) -> Option<&mut Option<PathBuf>> {
+        match name {
+            "cgroup" => Some(&mut self.cgroup_source),
+            "ipc" => Some(&mut self.ipc_source),
+            "mnt" => Some(&mut self.mnt_source),
+            "net" => Some(&mut self.net_source),
+            "pid" => Some(&mut self.pid_source),
+            "time" => Some(&mut self.time_source),
+            "uts" => Some(&mut self.uts_source),
+            "user" => Some(&mut self.user_source),
+            _ => None,
+        }
+    }
+
+    /// The proc path of the inherited file descriptor, which has to be open
+    /// and refer to a namespace of the provided name
+    ///
+    /// The namespace file system itself gets validated like the one of any
+    /// other namespace source.
+    fn bind_fd_source(&self, name: &str, fd: RawFd) -> Result<PathBuf> {
+        fcntl(fd, FcntlArg::F_GETFD).context(format!("file descriptor {} is not open", fd))?;
+        let source = self.proc_path.join("self/fd").join(fd.to_string());
+        let target = read_link(&source).context(format!("unable to read {}", source.display()))?;
+        if !target.to_string_lossy().starts_with(&format!("{}:[", name)) {
+            bail!(
+                "file descriptor {} refers to {} instead of a {} namespace",
+                fd,
+                target.display(),
+                name
+            )
+        }
+        Ok(source)
+    }
+
+    /// All requested features which are not supported by the running kernel
+    fn unsupported_kernel_features(&self) -> Vec<String> {
+        let mut unsupported = self
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled() && !namespace_supported(&self.proc_path, x.name()))
+            .map(|x| format!("{} namespace", x.name()))
+            .collect::<Vec<_>>();
+        if self.enters_namespace(self.namespaces().user())
+            && kernel::user_namespaces_disabled(&self.proc_path)
+        {
+            unsupported.push("user namespaces disabled via max_user_namespaces".into());
+        }
+        if self.require_cgroup_v2 && !cgroup_v2_host() {
+            unsupported.push("cgroup v2".into());
+        }
+        if self.no_symlinks && !self.no_pin && !kernel::openat2_supported() {
+            unsupported.push("openat2".into());
+        }
+        unsupported
+    }
+
+    /// Whether the namespace has to be unshared or joined, which is not the
+    /// case if it is disabled or gets pinned from a source file
+    pub fn enters_namespace(&self, namespace: &Namespace) -> bool {
+        namespace.enabled() && self.source_for_namespace(namespace.name()).is_none()
+    }
+
+    /// The namespaces to be unshared, in the order of unsharing them
+    pub fn namespaces_to_unshare(&self) -> Vec<Namespace> {
+        self.namespaces_to_enter(UNSHARE_ORDER)
+    }
+
+    /// The namespaces to be joined, in the order of joining them
+    pub fn namespaces_to_join(&self) -> Vec<Namespace> {
+        self.namespaces_to_enter(SETNS_ORDER)
+    }
+
+    /// The namespaces to be unshared or joined, in the provided order of their
+    /// names
+    fn namespaces_to_enter(&self, order: &[&str]) -> Vec<Namespace> {
+        order
+            .iter()
+            .filter_map(|name| self.namespaces().into_iter().find(|x| x.name == *name))
+            .filter(|x| self.enters_namespace(x))
+            .collect()
+    }
+
+    /// The path of the namespace to be bind mounted
+    pub fn namespace_source(&self, namespace: &Namespace) -> PathBuf {
+        if let Some(source) = self.source_for_namespace(namespace.name()) {
+            return source.clone();
+        }
+        match self.pin_pid() {
+            // The process itself already lives in the namespaces
+            Some(pid) => self.proc_namespace(&pid.to_string(), namespace.name()),
+            // The namespaces are unshared per thread, which matters if
+            // pinns is embedded into a multi-threaded application
+            None => self.proc_namespace("thread-self", namespace.proc_name()),
+        }
+    }
+
+    /// The path of the namespace `name` of a process below the proc path
+    pub fn proc_namespace(&self, process: &str, name: &str) -> PathBuf {
+        self.proc_path.join(process).join("ns").join(name)
+    }
+
+    /// Ensure that the namespaces of the process to be joined or pinned are
+    /// accessible
+    fn validate_target_pid(&self, pid: i32) -> Result<()> {
+        if !self.proc_path.join(pid.to_string()).is_dir() {
+            bail!("process {} does not exist", pid)
+        }
+        if self.uts_hostname.is_some() || !self.uid_map.is_empty() || !self.gid_map.is_empty() {
+            bail!("hostname and ID mappings cannot be set for an existing process")
+        }
+        if self.time_offset_monotonic.is_some() || self.time_offset_boottime.is_some() {
+            bail!("time offsets cannot be set for an existing process")
+        }
+        for ns in self.namespaces().into_iter().filter(|x| x.enabled()) {
+            let path = self.proc_namespace(&pid.to_string(), ns.name());
+            File::open(&path).context(format!("unable to read namespace {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Disable the namespaces of the process which are the same as the own ones
+    ///
+    /// Fails if this leaves no namespace to be pinned, because the check for
+    /// an empty selection already happened before.
+    fn disable_unchanged_namespaces(&mut self, pid: i32) -> Result<()> {
+        for name in NAMESPACE_NAMES {
+            let own = self.proc_namespace("thread-self", name);
+            let target = self.proc_namespace(&pid.to_string(), name);
+            let namespace = match self.namespaces.get_mut(name) {
+                Some(namespace) if namespace.enabled => namespace,
+                _ => continue,
+            };
+            if same_namespace(&own, &target)? {
+                debug!("skipping unchanged {} namespace of process {}", name, pid);
+                namespace.enabled = false;
+            }
+        }
+        if self.namespaces().into_iter().all(|x| !x.enabled()) {
+            bail!(
+                "all selected namespaces of process {} are the same as the own ones, \
+                 nothing left to pin",
+                pid
+            )
+        }
+        Ok(())
+    }
+
+    /// Create the directory if not existing and remember it for cleanup
+    fn create_dir_if_missing(&mut self, path: PathBuf, recursive: bool) -> Result<()> {
+        let missing = path
+            .ancestors()
+            .take_while(|x| !x.exists())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        is_dir_or_create(&path, recursive)?;
+        for dir in missing.into_iter().rev() {
+            // Set the permissions explicitly to be independent of the umask
+            set_permissions(&dir, Permissions::from_mode(self.dir_mode))
+                .context(format!("unable to set permissions of {}", dir.display()))?;
+            self.created_dirs.push(dir);
+        }
+        Ok(())
+    }
+}
+
+/// The namespace entry of the own process below the proc file system, which
+/// only exists if the running kernel supports the namespace
+fn kernel_namespace_path(proc_path: &Path, name: &str) -> PathBuf {
+    proc_path.join("self/ns").join(name)
+}
+
+/// The JSON schema of the configuration file provided via `--config`
+pub fn config_file_schema() -> Result<String> {
+    serde_json::to_string_pretty(&schema_for!(FileConfig)).context("unable to serialize schema")
+}
+
+/// Ensure that the source of a namespace is a namespace file
+///
+/// All namespace files live on the namespace file system, which means that
+/// they share the device with the namespace entries of the own process.
+fn validate_namespace_source(proc_path: &Path, name: &str, source: &Path) -> Result<()> {
+    let source_stat =
+        stat(source).context(format!("unable to stat namespace source {}", source.display()))?;
+    let nsfs_stat = stat(&kernel_namespace_path(proc_path, name))
+        .context(format!("{} namespace is not supported by the running kernel", name))?;
+    if source_stat.st_dev != nsfs_stat.st_dev {
+        bail!("namespace source {} is not a namespace file", source.display())
+    }
+    Ok(())
+}
+
+/// Whether both namespace files refer to the same namespace, which is the case
+/// if their device and inode numbers match
+fn same_namespace(a: &Path, b: &Path) -> Result<bool> {
+    let a = stat(a).context(format!("unable to stat {}", a.display()))?;
+    let b = stat(b).context(format!("unable to stat {}", b.display()))?;
+    Ok(a.st_dev == b.st_dev && a.st_ino == b.st_ino)
+}
+
+/// Whether the host uses the unified cgroup v2 hierarchy
+pub fn cgroup_v2_host() -> bool {
+    Path::new(CGROUP_V2_CONTROLLERS).exists()
+}
+
+/// Whether the running kernel supports the namespace of the provided name,
+/// according to the proc file system
+pub fn namespace_supported(proc_path: &Path, name: &str) -> bool {
+    kernel_namespace_path(proc_path, name).exists()
+}
+
+/// The directory below `dir` the namespace of the provided name is pinned to,
+/// like `netns` for the suffix `ns`
+pub fn parent_dir_for_namespace(dir: &Path, name: &str, suffix: &str) -> PathBuf {
+    dir.join(format!("{}{}", name, suffix))
+}
+
+fn parse_id_mappings(mappings: &[String]) -> Result<Vec<IdMapping>> {
+    mappings.iter().map(|x| x.parse()).collect()
+}
+
+/// Ensure that the file name stays within its parent directory
+fn validate_filename(filename: &str) -> Result<()> {
+    if filename.is_empty() || filename == "." || filename == ".." {
+        bail!("file name {:?} is not a valid file name", filename)
+    }
+    if filename.contains('/') {
+        bail!("file name {} must not contain path separators", filename)
+    }
+    Ok(())
+}
+
+/// Ensure that the subdirectory suffix keeps the subdirectories within their
+/// parent directory
+fn validate_dir_suffix(suffix: &str) -> Result<()> {
+    if suffix.contains('/') {
+        bail!("directory suffix {} must not contain path separators", suffix)
+    }
+    Ok(())
+}
+
+/// Read stdin until its end, unless a termination signal is received
+///
+/// The signal handlers interrupt reading, which `read_to_string` would retry.
+fn read_stdin() -> Result<String> {
+    let mut input = vec![];
+    let mut buf = [0; 4096];
+    let mut stdin = stdin();
+    loop {
+        match stdin.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => input.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {
+                if let Some(signal) = signal::received() {
+                    bail!("interrupted by {:?}", signal)
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    String::from_utf8(input).context("input is not valid UTF-8")
+}
+
+/// Parse a namespace name like `net`
+fn parse_namespace_name(s: &str) -> Result<String> {
+    match Namespace::from_name(s) {
+        Some(namespace) => Ok(namespace.name.into()),
+        None => bail!(
+            "unknown namespace {}, valid namespaces are: {}",
+            s,
+            NAMESPACE_NAMES.join(", ")
+        ),
+    }
+}
+
+/// Parse a namespace file descriptor to bind, like `net=3`
+fn parse_bind_fd(s: &str) -> Result<(String, RawFd)> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(fd)) => {
+            let fd = fd.parse().context(format!("file descriptor {} is not a number", fd))?;
+            Ok((parse_namespace_name(name)?, fd))
+        }
+        _ => bail!("invalid namespace file descriptor {}, expected NAMESPACE=FD", s),
+    }
+}
+
+/// Parse a flag to remount the pinned namespace files with, like `nosuid`
+fn parse_mount_flag(s: &str) -> Result<MsFlags> {
+    match MOUNT_FLAGS.iter().find(|(name, _)| *name == s) {
+        Some((_, flag)) => Ok(*flag),
+        None => bail!("unknown mount flag {}, valid flags are: nodev, noexec, nosuid", s),
+    }
+}
+
+/// Serialize the permissions in their octal notation, like `0755`
+fn serialize_mode<S: Serializer>(mode: &u32, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:04o}", mode))
+}
+
+/// Deserialize the permissions of the config file from their octal notation
+fn deserialize_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    parse_mode(&String::deserialize(d)?)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Deserialize the mount flags of the config file by their names
+fn deserialize_mount_flags<'de, D>(d: D) -> Result<Option<Vec<MsFlags>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|x| parse_mount_flag(x))
+        .collect::<Result<_>>()
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Serialize the mount flags by the names they have been provided with
+fn serialize_mount_flags<S: Serializer>(flags: &[MsFlags], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(
+        flags
+            .iter()
+            .filter_map(|x| MOUNT_FLAGS.iter().find(|(_, flag)| flag == x))
+            .map(|(name, _)| name),
+    )
+}
+
+/// Serialize the names of the enabled namespaces
+fn serialize_namespaces<S: Serializer>(namespaces: &Namespaces, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(namespaces.into_iter().filter(|x| x.enabled()).map(|x| x.name))
+}
+
+/// Parse an OOM score adjustment, which the kernel accepts within ±1000
+fn parse_oom_score_adj(s: &str) -> Result<i32> {
+    let score = s.parse::<i32>().context(format!("OOM score {} is not a number", s))?;
+    if !(-1000..=1000).contains(&score) {
+        bail!("OOM score {} is not between -1000 and 1000", s)
+    }
+    Ok(score)
+}
+
+/// Whether the `NO_COLOR` environment variable is set to a non empty value
+fn no_color_env() -> bool {
+    var_os("NO_COLOR").map_or(false, |x| !x.is_empty())
+}
+
+/// Parse the value of a flag provided by an environment variable
+fn parse_flag(s: &str) -> Result<bool> {
+    match s {
+        "1" | "true" => Ok(true),
+        "" | "0" | "false" => Ok(false),
+        _ => bail!("{} is neither `1`, `true`, `0` nor `false`", s),
+    }
+}
+
+/// Parse an octal file mode like `0755`
+fn parse_mode(s: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(s, 8).context(format!("mode {} is not octal", s))?;
+    if mode > 0o7777 {
+        bail!("mode {} exceeds 07777", s)
+    }
+    Ok(mode)
+}
+
+fn is_existing_dir(path: &Path) -> Result<()> {
+    if !path.is_dir() {
+        bail!("given path {} is not an existing directory", path.display())
+    }
+    Ok(())
+}
+
+fn is_dir_if_existing(path: &Path) -> Result<()> {
+    if path.exists() && !path.is_dir() {
+        bail!("given path {} is not a directory", path.display())
+    }
+    Ok(())
+}
+
+fn is_dir_or_create(path: &Path, recursive: bool) -> Result<()> {
+    if !path.exists() {
+        create_missing_dir(path, recursive)?;
+    }
+    // Stat again, because a concurrent run may have created anything
+    if !metadata(path)?.is_dir() {
+        bail!("given path {} is not a directory", path.display())
+    }
+    Ok(())
+}
+
+/// Create the directory, which may have been created by a concurrent run in
+/// the meantime
+fn create_missing_dir(path: &Path, recursive: bool) -> Result<()> {
+    let res = if recursive {
+        create_dir_all(path)
+    } else {
+        create_dir(path)
+    };
+    match res {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            debug!("directory {} created concurrently", path.display());
+            Ok(())
+        }
+        res => res.context(format!("unable to create directory {}", path.display())),
+    }
+}
+
+impl Default for Config {
+    /// The configuration equal to the command line defaults
+    ///
+    /// The values are set directly rather than by parsing empty arguments, so
+    /// that neither clap nor the `PINNS_*` environment variables are involved.
+    fn default() -> Self {
+        Self {
+            config_file: None,
+            log_level: LevelFilter::Info,
+            quiet: false,
+            version_json: false,
+            no_color: false,
+            log_format: LogFormat::Text,
+            log_file: None,
+            dir: PathBuf::from(TEMP_DIR.as_str()),
+            filename: TEMP_FILE.clone(),
+            cgroup_filename: None,
+            ipc_filename: None,
+            mnt_filename: None,
+            net_filename: None,
+            pid_filename: None,
+            time_filename: None,
+            uts_filename: None,
+            user_filename: None,
+            cgroup_dir: None,
+            ipc_dir: None,
+            mnt_dir: None,
+            net_dir: None,
+            pid_dir: None,
+            time_dir: None,
+            uts_dir: None,
+            user_dir: None,
+            cgroup_source: None,
+            ipc_source: None,
+            mnt_source: None,
+            net_source: None,
+            pid_source: None,
+            time_source: None,
+            uts_source: None,
+            user_source: None,
+            bind_fd: vec![],
+            unique_per_namespace: false,
+            flat: false,
+            dir_suffix: "ns".into(),
+            all: false,
+            cgroup: false,
+            ipc: false,
+            mnt: false,
+            net: false,
+            pid: false,
+            time: false,
+            uts: false,
+            user: false,
+            from_stdin: false,
+            from_oci_spec: None,
+            namespace_list: vec![],
+            disable: vec![],
+            output_format: OutputFormat::Text,
+            include_inode: false,
+            dir_mode: 0o755,
+            file_mode: 0o644,
+            uid: None,
+            gid: None,
+            mkdir: false,
+            no_pin: false,
+            force: false,
+            overwrite_existing_empty: false,
+            new_keyring: false,
+            timing: false,
+            read_only: false,
+            mount_flags: vec![],
+            verify: false,
+            no_symlinks: false,
+            strict: false,
+            strict_kernel_check: false,
+            mount_tmpfs: false,
+            print_config: false,
+            dry_run: false,
+            proc_path: "/proc".into(),
+            pin_paths_file: None,
+            metadata_file: None,
+            index: false,
+            pass_fd_socket: None,
+            mount_retries: 0,
+            mount_retry_delay: 100,
+            jobs: 0,
+            propagation: Propagation::Unchanged,
+            selinux_label: None,
+            uts_hostname: None,
+            time_offset_monotonic: None,
+            time_offset_boottime: None,
+            require_cgroup_v2: false,
+            cgroup_path: None,
+            join_pid: None,
+            pin_pid: None,
+            skip_unchanged: false,
+            uid_map: vec![],
+            gid_map: vec![],
+            subcommand: None,
+            namespaces: Namespaces::default(),
+            uid_mappings: vec![],
+            gid_mappings: vec![],
+            created_dirs: vec![],
+            matches: None,
+        }
+    }
+}
+
+/// A builder for creating a configuration without command line parsing
+#[derive(Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// The parent directory for the pinned namespaces
+    pub fn dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.config.dir = dir.into();
+        self
+    }
+
+    /// The file name of the pinned namespaces
+    pub fn filename<S: Into<String>>(mut self, filename: S) -> Self {
+        self.config.filename = filename.into();
+        self
+    }
+
+    /// Pin the namespace of the provided name, for example `net`
+    pub fn namespace(mut self, name: &str) -> Result<Self> {
+        self.config.enable_namespaces(name)?;
+        Ok(self)
+    }
+
+    /// Pin all available namespaces
+    pub fn all(mut self, all: bool) -> Self {
+        self.config.all = all;
+        self
+    }
+
+    /// The logging level of the application
+    pub fn log_level(mut self, log_level: LevelFilter) -> Self {
+        self.config.log_level = log_level;
+        self
+    }
+
+    /// Create missing parent directories of `dir`
+    pub fn mkdir(mut self, mkdir: bool) -> Self {
+        self.config.mkdir = mkdir;
+        self
+    }
+
+    /// The octal permissions of the created directories
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.config.dir_mode = mode;
+        self
+    }
+
+    /// The octal permissions of the created namespace files
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.config.file_mode = mode;
+        self
+    }
+
+    /// Only unshare the namespaces without bind mounting them
+    pub fn no_pin(mut self, no_pin: bool) -> Self {
+        self.config.no_pin = no_pin;
+        self
+    }
+
+    /// Print the namespaces to be unshared and bind mounted without doing it
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Create the configuration
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use nix::{
+        fcntl::{open, OFlag},
+        sys::stat::Mode,
+        unistd::close,
+    };
+    use std::{
+        fs::{write, File},
+        io::Write,
+    };
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn validate_success() -> Result<()> {
+        let mut c = Config::default();
+        c.cgroup = true;
+        c.validate()
+    }
+
+    #[test]
+    fn validate_success_user() -> Result<()> {
+        let mut c = Config::default();
+        c.user = true;
+        c.validate()?;
+        assert!(c.namespaces().user().enabled());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_mnt() -> Result<()> {
+        let mut c = Config::default();
+        c.mnt = true;
+        c.validate()?;
+        c.prepare_dirs()?;
+        assert!(c.namespaces().mnt().enabled());
+        assert!(c.parent_dir_for_namespace("mnt").is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_id_mappings() -> Result<()> {
+        let mut c = Config::default();
+        c.user = true;
+        c.uid_map = vec!["0:1000:1".into(), "1:100000:65536".into()];
+        c.gid_map = vec!["0:1000:1".into()];
+        c.validate()?;
+        assert_eq!(c.uid_mappings().len(), 2);
+        assert_eq!(c.uid_mappings()[1].to_string(), "1 100000 65536");
+        assert_eq!(c.gid_mappings()[0].host_id(), 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_malformed_id_mappings() {
+        for mapping in &["0:1000", "0:1000:1:2", "a:1000:1", "0:1000:0", ""] {
+            let mut c = Config::default();
+            c.user = true;
+            c.uid_map = vec![mapping.to_string()];
+            assert!(c.validate().is_err())
+        }
+    }
+
+    #[test]
+    fn validate_failed_id_mappings_without_user() {
+        let mut c = Config::default();
+        c.net = true;
+        c.gid_map = vec!["0:1000:1".into()];
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_success_uts_hostname() -> Result<()> {
+        let mut c = Config::default();
+        c.uts = true;
+        c.uts_hostname = Some("pinned".into());
+        c.validate()
+    }
+
+    #[test]
+    fn validate_selinux_label() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.selinux_label = Some("system_u:object_r:container_file_t:s0".into());
+        c.validate()?;
+
+        c.selinux_label = Some("container_file_t".into());
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_uts_hostname_without_uts() {
+        let mut c = Config::default();
+        c.net = true;
+        c.uts_hostname = Some("pinned".into());
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_uts_hostname_too_long() {
+        let mut c = Config::default();
+        c.uts = true;
+        c.uts_hostname = Some("a".repeat(HOST_NAME_MAX + 1));
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_time_offset_without_time() {
+        let mut c = Config::default();
+        c.net = true;
+        c.time_offset_monotonic = Some(10);
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_time_offset_out_of_range() {
+        let mut c = Config::default();
+        c.time = true;
+        c.time_offset_boottime = Some(i64::min_value());
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_success_owner_self() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.uid = Some(Uid::effective().as_raw());
+        c.gid = Some(Gid::effective().as_raw());
+        c.validate()
+    }
+
+    #[test]
+    fn validate_require_cgroup_v2() {
+        let mut c = Config::default();
+        c.cgroup = true;
+        c.require_cgroup_v2 = true;
+        assert_eq!(c.validate().is_ok(), cgroup_v2_host())
+    }
+
+    #[test]
+    fn validate_cgroup_path() -> Result<()> {
+        let mut c = Config::default();
+        c.cgroup = true;
+        let dir = tempdir()?;
+        c.cgroup_path = Some(dir.path().into());
+        assert!(c.validate().is_err());
+
+        if cgroup_v2_host() {
+            c.cgroup_path = Some("/sys/fs/cgroup".into());
+            c.validate()?;
+        }
+
+        c.cgroup = false;
+        c.net = true;
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_kernel_features_success() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("sys/user"))?;
+        write(dir.path().join("sys/user/max_user_namespaces"), "0\n")?;
+
+        let mut c = Config::default();
+        c.net = true;
+        c.validate()?;
+        assert!(c.unsupported_kernel_features().is_empty());
+
+        c.proc_path = dir.path().into();
+        c.namespaces.user.enabled = true;
+        assert_eq!(
+            c.unsupported_kernel_features(),
+            vec!["user namespaces disabled via max_user_namespaces"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_require_cgroup_v2_without_cgroup() {
+        let mut c = Config::default();
+        c.net = true;
+        c.require_cgroup_v2 = true;
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_owner_uid_without_gid() {
+        let mut c = Config::default();
+        c.net = true;
+        c.uid = Some(Uid::effective().as_raw());
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_owner_unprivileged() {
+        let mut c = Config::default();
+        c.net = true;
+        c.uid = Some(Uid::effective().as_raw() + 1);
+        c.gid = Some(Gid::effective().as_raw());
+        assert_eq!(c.validate().is_err(), !Uid::effective().is_root())
+    }
+
+    #[test]
+    fn prepare_dirs_success_created_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().join("pins");
+        c.ipc = true;
+        c.validate()?;
+        assert!(!c.dir().exists());
+        c.prepare_dirs()?;
+        assert_eq!(c.created_dirs(), &[c.dir.clone(), c.parent_dir_for_namespace("ipc")]);
+        Ok(())
+    }
+
+    fn config_file(content: &str) -> Result<NamedTempFile> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(content.as_bytes())?;
+        Ok(file)
+    }
+
+    #[test]
+    fn config_file_schema_success() -> Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&config_file_schema()?)?;
+        let properties = &schema["properties"];
+        for key in &["dir", "filename", "net", "uts", "log_level", "uid_map", "namespaces"] {
+            assert!(properties.get(key).is_some(), "missing {}", key);
+        }
+        assert_eq!(schema["additionalProperties"], false);
+        Ok(())
+    }
+
+    #[test]
+    fn config_file_schema_covers_options() -> Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&config_file_schema()?)?;
+        let properties = &schema["properties"];
+        // The namespace list is named `namespaces` in the file
+        let excluded = ["config_file", "from_stdin", "bind_fd", "namespace_list"];
+        let config = serde_json::to_value(Config::default())?;
+        for key in config.as_object().into_iter().flat_map(|x| x.keys()) {
+            if !excluded.contains(&key.as_str()) {
+                assert!(properties.get(key).is_some(), "missing {}", key);
+            }
+        }
+        assert_eq!(
+            schema["definitions"]["Propagation"]["enum"],
+            serde_json::json!(["private", "shared", "slave", "unchanged"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_success() -> Result<()> {
+        let file = config_file(
+            r#"{"filename": "pinned", "net": true, "uts": true, "dir_mode": "0700",
+                "mount_flags": ["nosuid"], "mount_retries": 3, "selinux_label": "a:b:c"}"#,
+        )?;
+        let path = file.path().display().to_string();
+        let mut c = Config::parse_args_from(vec!["pinns", "--config", &path]);
+        c.merge_config_file()?;
+        assert_eq!(c.filename(), "pinned");
+        assert!(c.net && c.uts && !c.ipc);
+        assert_eq!(c.dir_mode(), 0o700);
+        assert_eq!(c.remount_flags(), MsFlags::MS_NOSUID);
+        assert_eq!(c.mount_retries(), 3);
+        assert_eq!(c.selinux_label().as_deref(), Some("a:b:c"));
+        c.validate()
+    }
+
+    #[test]
+    fn merge_config_file_cli_overrides() -> Result<()> {
+        let file = config_file(
+            r#"{"filename": "pinned", "log_level": "debug", "output_format": "json"}"#,
+        )?;
+        let path = file.path().display().to_string();
+        let mut c = Config::parse_args_from(vec![
+            "pinns",
+            "--config",
+            &path,
+            "--filename",
+            "overridden",
+            "--output-format",
+            "text",
+        ]);
+        c.merge_config_file()?;
+        assert_eq!(c.filename(), "overridden");
+        assert_eq!(c.output_format(), OutputFormat::Text);
+        assert_eq!(c.log_level(), LevelFilter::Debug);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_cli_replaces_namespaces() -> Result<()> {
+        let file = config_file(r#"{"net": true, "namespaces": ["uts"]}"#)?;
+        let path = file.path().display().to_string();
+        let mut c = Config::parse_args_from(vec!["pinns", "--config", &path, "--ipc"]);
+        c.merge_config_file()?;
+        c.validate()?;
+        let enabled = c
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled())
+            .map(|x| *x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(enabled, vec!["ipc"]);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_cli_overrides_multi_word_options() -> Result<()> {
+        let file = config_file(r#"{"mnt_filename": "file", "dir_suffix": "file"}"#)?;
+        let path = file.path().display().to_string();
+        let mut c = Config::parse_args_from(vec![
+            "pinns",
+            "--config",
+            &path,
+            "--mount-filename",
+            "overridden",
+        ]);
+        c.merge_config_file()?;
+        assert_eq!(c.mnt_filename.as_deref(), Some("overridden"));
+        assert_eq!(c.dir_suffix(), "file");
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_cli_namespace_list_replaces_namespaces() -> Result<()> {
+        let file = config_file(r#"{"net": true}"#)?;
+        let path = file.path().display().to_string();
+        let mut c =
+            Config::parse_args_from(vec!["pinns", "--config", &path, "--namespaces", "ipc"]);
+        c.merge_config_file()?;
+        c.validate()?;
+        let enabled = c
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled())
+            .map(|x| *x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(enabled, vec!["ipc"]);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_failed_unknown_key() -> Result<()> {
+        let file = config_file(r#"{"nett": true}"#)?;
+        let mut c = Config::default();
+        c.config_file = Some(file.path().into());
+        assert!(c.merge_config_file().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_file_failed_not_existing() {
+        let mut c = Config::default();
+        c.config_file = Some("/not/existing/config.json".into());
+        assert!(c.merge_config_file().is_err())
+    }
+
+    #[test]
+    fn validate_success_join_pid() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.join_pid = Some(std::process::id() as i32);
+        c.validate()
+    }
+
+    #[test]
+    fn validate_failed_join_pid_not_existing() {
+        let mut c = Config::default();
+        c.net = true;
+        c.join_pid = Some(-1);
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_join_pid_with_hostname() {
+        let mut c = Config::default();
+        c.uts = true;
+        c.uts_hostname = Some("pinned".into());
+        c.join_pid = Some(std::process::id() as i32);
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn log_level_quiet() {
+        let mut c = Config::default();
+        c.log_level = LevelFilter::Debug;
+        assert_eq!(c.log_level(), LevelFilter::Debug);
+        c.quiet = true;
+        assert_eq!(c.log_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    fn builder_success() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::builder()
+            .dir(dir.path())
+            .filename("pinned")
+            .namespace("net")?
+            .namespace("uts")?
+            .build();
+        c.validate()?;
+        assert!(c.namespaces().net().enabled());
+        assert!(c.namespaces().uts().enabled());
+        assert!(!c.namespaces().ipc().enabled());
+        assert_eq!(c.filename(), "pinned");
+        Ok(())
+    }
+
+    #[test]
+    fn default_equals_command_line_defaults() -> Result<()> {
+        // The directory is passed explicitly, because clap would read it from
+        // `PINNS_DIR` otherwise
+        let parsed = Config::parse_from(&["pinns", "--dir", TEMP_DIR.as_str()]);
+        assert_eq!(
+            serde_json::to_value(Config::default())?,
+            serde_json::to_value(parsed)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builder_failed_unknown_namespace() {
+        assert!(Config::builder().namespace("foo").is_err())
+    }
+
+    #[test]
+    fn for_request_success() -> Result<()> {
+        let dir = tempdir()?;
+        let daemon = Config::parse_from(&["pinns", "--net", "--file-mode", "0600", "--mkdir"]);
+        let mut c = daemon.for_request(dir.path().into(), None, &["uts".into()])?;
+        assert_eq!(c.filename(), "{uuid}");
+        c.validate()?;
+        assert_eq!(c.dir(), dir.path());
+        assert_eq!(c.file_mode(), 0o600);
+        assert!(c.mkdir);
+        assert!(c.namespaces().uts().enabled());
+        assert!(!c.namespaces().net().enabled());
+
+        let c = daemon.for_request(dir.path().into(), Some("pinned".into()), &[])?;
+        assert_eq!(c.filename(), "pinned");
+        assert!(daemon.for_request(dir.path().into(), None, &["foo".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn enable_namespaces_success() -> Result<()> {
+        let mut c = Config::default();
+        c.enable_namespaces("net,uts\n ipc \n\n")?;
+        let enabled = c
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled())
+            .map(|x| *x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(enabled, vec!["ipc", "net", "uts"]);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_all_disable() -> Result<()> {
+        let mut c = Config::try_parse_from(&["pinns", "--all", "--disable", "net,ipc"])?;
+        c.validate()?;
+        assert!(!c.namespaces().net().enabled());
+        assert!(!c.namespaces().ipc().enabled());
+        assert!(c.namespaces().uts().enabled());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_disable_everything() -> Result<()> {
+        let mut c = Config::try_parse_from(&["pinns", "--net", "--uts", "--disable", "uts,net"])?;
+        let err = c.validate().unwrap_err();
+        assert_eq!(err.to_string(), "no namespace specified for pinning");
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_from_name() {
+        for name in NAMESPACE_NAMES {
+            assert_eq!(Namespace::from_name(name).map(|x| x.name), Some(*name));
+        }
+        assert_eq!(Namespace::from_name("pid").map(|x| x.proc_name), Some("pid_for_children"));
+        assert_eq!(Namespace::from_name("time").map(|x| x.proc_name), Some("time_for_children"));
+        assert!(Namespace::from_name("foo").is_none());
+    }
+
+    #[test]
+    fn validate_success_namespace_list() -> Result<()> {
+        let mut c = Config::parse_from(&["pinns", "--namespaces", "net,pid", "--uts"]);
+        c.validate()?;
+        let enabled = c
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled())
+            .map(|x| *x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(enabled, vec!["net", "pid", "uts"]);
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_list_failed_unknown() {
+        assert!(Config::try_parse_from(&["pinns", "--namespaces", "net,foo"]).is_err())
+    }
+
+    #[test]
+    fn enable_namespaces_failed_unknown() {
+        let mut c = Config::default();
+        let err = c.enable_namespaces("net\nfoo").unwrap_err();
+        assert!(err.to_string().contains("cgroup, ipc, mnt"));
+    }
+
+    #[test]
+    fn bind_path_for_namespace_dir_override() {
+        let mut c = Config::default();
+        c.dir = "/run/pinns".into();
+        c.filename = "pinned".into();
+        c.net_dir = Some("/run/netns".into());
+        assert_eq!(c.bind_path_for_namespace("net"), Path::new("/run/netns/netns/pinned"));
+        assert_eq!(c.bind_path_for_namespace("uts"), Path::new("/run/pinns/utsns/pinned"));
+        c.flat = true;
+        assert_eq!(c.bind_path_for_namespace("net"), Path::new("/run/netns/pinned"));
+    }
+
+    #[test]
+    fn validate_failed_namespace_dir_not_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.net = true;
+        c.net_dir = Some(dir.path().join("missing"));
+        assert!(c.validate().is_err());
+        c.mkdir = true;
+        c.validate()
+    }
+
+    #[test]
+    fn filename_for_namespace_fallback() {
+        let mut c = Config::default();
+        c.filename = "pinned".into();
+        assert_eq!(c.filename_for_namespace("net"), "pinned");
+        assert_eq!(c.filename_for_namespace("uts"), "pinned");
+    }
+
+    #[test]
+    fn bind_path_for_namespace_override() {
+        let mut c = Config::default();
+        c.dir = "/run/pinns".into();
+        c.filename = "pinned".into();
+        c.net_filename = Some("network".into());
+        assert_eq!(c.bind_path_for_namespace("net"), Path::new("/run/pinns/netns/network"));
+        assert_eq!(c.bind_path_for_namespace("uts"), Path::new("/run/pinns/utsns/pinned"));
+    }
+
+    #[test]
+    fn unpin_umount_flags() -> Result<()> {
+        let unpin = |args: &[&str]| -> Result<MntFlags> {
+            let c = Config::try_parse_from(["pinns", "unpin", "-f", "x"].iter().chain(args))?;
+            match c.subcommand() {
+                Some(SubCommand::Unpin(unpin)) => Ok(unpin.umount_flags()),
+                _ => bail!("no unpin subcommand"),
+            }
+        };
+        assert_eq!(unpin(&["net"])?, MntFlags::MNT_DETACH);
+        assert_eq!(unpin(&["--detach", "false", "net"])?, MntFlags::empty());
+        Ok(())
+    }
+
+    #[test]
+    fn filename_for_namespace_override() {
+        let mut c = Config::default();
+        c.filename = "pinned".into();
+        c.net_filename = Some("network".into());
+        assert_eq!(c.filename_for_namespace("net"), "network");
+        assert_eq!(c.filename_for_namespace("uts"), "pinned");
+    }
+
+    #[test]
+    fn validate_success_unique_per_namespace() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.uts = true;
+        c.unique_per_namespace = true;
+        c.ipc_filename = Some("ipc".into());
+        c.validate()?;
+        assert_ne!(c.filename_for_namespace("net"), c.filename_for_namespace("uts"));
+        assert_ne!(c.filename_for_namespace("net"), c.filename());
+        assert_eq!(c.filename_for_namespace("ipc"), "ipc");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_flat() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().into();
+        c.net = true;
+        c.uts = true;
+        c.flat = true;
+        c.unique_per_namespace = true;
+        c.validate()?;
+        assert_eq!(c.parent_dir_for_namespace("net"), dir.path());
+        assert_eq!(c.parent_dir_for_namespace("uts"), dir.path());
+        Ok(())
+    }
+
+    #[test]
+    fn bind_path_for_namespace_dir_suffix() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let args = &["pinns", "-d", pin_dir.as_str(), "-f", "x", "--dir-suffix", ""];
+        let mut c = Config::try_parse_from(args)?;
+        c.net = true;
+        c.validate()?;
+        assert_eq!(c.bind_path_for_namespace("net"), dir.path().join("net").join("x"));
+
+        c.dir_suffix = "-ns".into();
+        assert_eq!(c.bind_path_for_namespace("net"), dir.path().join("net-ns").join("x"));
+
+        c.dir_suffix = "ns/..".into();
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unpin_bind_path_for_namespace_dir_suffix() -> Result<()> {
+        let args = &["pinns", "unpin", "-d", "/run", "-f", "x", "--dir-suffix", "", "net"];
+        let c = Config::try_parse_from(args)?;
+        match c.subcommand() {
+            Some(SubCommand::Unpin(unpin)) => {
+                assert_eq!(unpin.bind_path_for_namespace("net"), Path::new("/run/net/x"))
+            }
+            _ => bail!("no unpin subcommand"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_flat_same_filename() {
+        let mut c = Config::default();
+        c.net = true;
+        c.uts = true;
+        c.flat = true;
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn log_format_from_str() -> Result<()> {
+        assert_eq!("text".parse::<LogFormat>()?, LogFormat::Text);
+        assert_eq!("json".parse::<LogFormat>()?, LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn propagation_from_str() -> Result<()> {
+        assert_eq!("private".parse::<Propagation>()?.flag(), Some(MsFlags::MS_PRIVATE));
+        assert_eq!("shared".parse::<Propagation>()?.flag(), Some(MsFlags::MS_SHARED));
+        assert_eq!("slave".parse::<Propagation>()?.flag(), Some(MsFlags::MS_SLAVE));
+        assert_eq!("unchanged".parse::<Propagation>()?.flag(), None);
+        assert!("unbindable".parse::<Propagation>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_all() -> Result<()> {
+        let mut c = Config::default();
+        c.all = true;
+        c.net = true;
+        c.validate()?;
+        assert!(c
+            .namespaces()
+            .into_iter()
+            .all(|x| x.enabled() == namespace_supported(c.proc_path(), x.name())));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_namespace_missing_in_proc_path() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.proc_path = dir.path().into();
+        c.net = true;
+        assert_eq!(
+            c.validate().unwrap_err().to_string(),
+            format!(
+                "net namespace is not supported by the running kernel, {} does not exist",
+                dir.path().join("self/ns/net").display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn namespaces_to_unshare_user_first() -> Result<()> {
+        let mut c = Config::default();
+        c.pid = true;
+        c.net = true;
+        c.user = true;
+        c.validate()?;
+        let names = c.namespaces_to_unshare().iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(names, ["user", "net", "pid"]);
+        Ok(())
+    }
+
+    #[test]
+    fn namespaces_to_unshare_all_in_order() -> Result<()> {
+        let mut c = Config::default();
+        c.all = true;
+        c.validate()?;
+        let names = c.namespaces_to_unshare().iter().map(|x| x.name).collect::<Vec<_>>();
+        let expected = UNSHARE_ORDER
+            .iter()
+            .filter(|x| namespace_supported(c.proc_path(), x))
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn namespaces_to_join_mnt_after_net() -> Result<()> {
+        let mut c = Config::default();
+        c.mnt = true;
+        c.net = true;
+        c.uts = true;
+        c.user = true;
+        c.validate()?;
+        let names = c.namespaces_to_join().iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(names, ["user", "uts", "net", "mnt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bind_fd_success() -> Result<()> {
+        assert_eq!(parse_bind_fd("net=3")?, ("net".into(), 3));
+        assert!(parse_bind_fd("net").is_err());
+        assert!(parse_bind_fd("net=x").is_err());
+        assert!(parse_bind_fd("nett=3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_bind_fd() -> Result<()> {
+        let fd = open("/proc/self/ns/net", OFlag::O_RDONLY, Mode::empty())?;
+        let mut c = Config::default();
+        c.bind_fd = vec![("net".into(), fd)];
+        let res = c.validate();
+        let mut wrong = Config::default();
+        wrong.bind_fd = vec![("uts".into(), fd)];
+        let wrong_res = wrong.validate();
+        close(fd)?;
+
+        res?;
+        assert!(c.namespaces().net().enabled());
+        assert!(!c.enters_namespace(c.namespaces().net()));
+        assert_eq!(
+            c.namespace_source(c.namespaces().net()),
+            Path::new("/proc/self/fd").join(fd.to_string())
+        );
+        assert!(wrong_res.is_err());
+
+        let mut closed = Config::default();
+        closed.bind_fd = vec![("net".into(), RawFd::max_value())];
+        assert!(closed.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_net_source() -> Result<()> {
+        let mut c = Config::default();
+        c.net_source = Some("/proc/self/ns/net".into());
+        c.validate()?;
+        assert!(c.namespaces().net().enabled());
+        assert!(!c.enters_namespace(c.namespaces().net()));
+        assert_eq!(c.namespace_source(c.namespaces().net()), PathBuf::from("/proc/self/ns/net"));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_net_source_no_namespace_file() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let mut c = Config::default();
+        c.net_source = Some(file.path().into());
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_uts_source_with_hostname() {
+        let mut c = Config::default();
+        c.uts_source = Some("/proc/self/ns/uts".into());
+        c.uts_hostname = Some("pinned".into());
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_new_keyring_without_user() {
+        let mut c = Config::default();
+        c.net = true;
+        c.new_keyring = true;
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_success_pin_pid() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.pin_pid = Some(std::process::id() as i32);
+        c.validate()?;
+        assert_eq!(
+            c.namespace_source(c.namespaces().net()),
+            PathBuf::from(format!("/proc/{}/ns/net", std::process::id()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_proc_path_not_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.net = true;
+        c.proc_path = dir.path().into();
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_source_proc_path() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("self").join("ns"))?;
+        let mut c = Config::default();
+        c.net = true;
+        c.proc_path = dir.path().into();
+        c.validate()?;
+        assert_eq!(
+            c.namespace_source(c.namespaces().net()),
+            dir.path().join("thread-self").join("ns").join("net")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_skip_unchanged_all() {
+        let mut c = Config::default();
+        c.net = true;
+        c.uts = true;
+        c.pin_pid = Some(std::process::id() as i32);
+        c.skip_unchanged = true;
+        let err = c.validate().unwrap_err();
+        assert!(err.to_string().contains("nothing left to pin"));
+    }
+
+    #[test]
+    fn validate_failed_skip_unchanged_without_pid() {
+        let mut c = Config::default();
+        c.net = true;
+        c.skip_unchanged = true;
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn same_namespace_success() -> Result<()> {
+        let own = Path::new("/proc/self/ns/net");
+        let target = PathBuf::from(format!("/proc/{}/ns/net", std::process::id()));
+        assert!(same_namespace(own, &target)?);
+        assert!(!same_namespace(own, Path::new("/proc/self/ns/uts"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_pin_pid_not_existing() {
+        let mut c = Config::default();
+        c.net = true;
+        c.pin_pid = Some(-1);
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_pin_pid_and_join_pid() {
+        let mut c = Config::default();
+        c.net = true;
+        c.pin_pid = Some(std::process::id() as i32);
+        c.join_pid = Some(std::process::id() as i32);
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_success_no_pin() -> Result<()> {
+        let mut c = Config::default();
+        c.dir = PathBuf::from("/not/existing/path");
+        c.net = true;
+        c.no_pin = true;
+        c.validate()?;
+        assert!(!c.dir().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_no_pin_no_namespaces() {
+        let mut c = Config::default();
+        c.no_pin = true;
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_no_namespaces() {
+        let mut c = Config::default();
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn prepare_dirs_success_mkdir() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().join("a").join("b").join("c");
+        c.mkdir = true;
+        c.net = true;
+        c.validate()?;
+        c.prepare_dirs()?;
+        assert!(c.parent_dir_for_namespace("net").is_dir());
+        assert_eq!(c.created_dirs().first(), Some(&dir.path().join("a")));
+        assert_eq!(c.created_dirs().len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_dirs_success_dir_mode() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().join("pins");
+        c.dir_mode = 0o750;
+        c.net = true;
+        c.validate()?;
+        c.prepare_dirs()?;
+        for dir in c.created_dirs() {
+            assert_eq!(metadata(dir)?.permissions().mode() & 0o7777, 0o750);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_filename_uuid() -> Result<()> {
+        let mut c = Config::default();
+        c.net = true;
+        c.filename = "pinned-{uuid}".into();
+        c.validate()?;
+        assert!(c.filename().starts_with("pinned-"));
+        assert_eq!(c.filename().len(), "pinned-".len() + 36);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_filename_index() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().into();
+        c.net = true;
+        c.uts = true;
+        c.filename = "pinned-{index}".into();
+        create_dir_all(c.parent_dir_for_namespace("uts"))?;
+        File::create(c.parent_dir_for_namespace("uts").join("pinned-0"))?;
+        c.validate()?;
+        assert_eq!(c.filename(), "pinned-1");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success_namespace_filename_templates() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().into();
+        c.net = true;
+        c.uts = true;
+        c.filename = "pinned-{index}".into();
+        c.net_filename = Some("net-{index}-{uuid}".into());
+        c.uts_filename = Some("uts-{index}".into());
+        create_dir_all(c.parent_dir_for_namespace("uts"))?;
+        File::create(c.parent_dir_for_namespace("uts").join("uts-0"))?;
+        c.validate()?;
+        assert_eq!(c.filename(), "pinned-1");
+        assert!(c.filename_for_namespace("net").starts_with("net-1-"));
+        assert_eq!(c.filename_for_namespace("net").len(), "net-1-".len() + 36);
+        assert_eq!(c.filename_for_namespace("uts"), "uts-1");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_filename_separator() {
+        let mut c = Config::default();
+        c.net = true;
+        c.filename = "{index}/pinned".into();
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_failed_filename_traversal() {
+        let mut c = Config::default();
+        c.net = true;
+        c.filename = "../../etc/passwd".into();
+        assert!(c.validate().is_err());
+
+        c.filename = "..".into();
+        assert!(c.validate().is_err());
+    }
+
+    #[test]
+    fn validate_failed_namespace_filename_traversal() {
+        let mut c = Config::default();
+        c.net = true;
+        c.filename = "pinned".into();
+        c.net_filename = Some("../pinned".into());
+        assert!(c.validate().is_err())
+    }
+
+    #[test]
+    fn validate_filename_success() -> Result<()> {
+        validate_filename("pinned")?;
+        validate_filename("..pinned")
+    }
+
+    #[test]
+    fn parse_mode_success() -> Result<()> {
+        assert_eq!(parse_mode("0755")?, 0o755);
+        assert_eq!(parse_mode("644")?, 0o644);
+        assert!(parse_mode("0999").is_err());
+        assert!(parse_mode("17777").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_oom_score_adj_range() -> Result<()> {
+        assert_eq!(parse_oom_score_adj("-1000")?, -1000);
+        assert_eq!(parse_oom_score_adj("1000")?, 1000);
+        assert!(parse_oom_score_adj("1001").is_err());
+        assert!(parse_oom_score_adj("-1001").is_err());
+        assert!(parse_oom_score_adj("high").is_err());
+
+        // Only the exec subcommand accepts the option
+        assert!(Config::try_parse_from(&["pinns", "exec", "--oom-score-adj", "-5", "sh"]).is_ok());
+        assert!(Config::try_parse_from(&["pinns", "--oom-score-adj", "5", "--net"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exec_cap_drop() -> Result<()> {
+        let c =
+            Config::try_parse_from(&["pinns", "exec", "--cap-drop", "net_raw,CAP_CHOWN", "sh"])?;
+        match c.subcommand() {
+            Some(SubCommand::Exec(exec)) => assert_eq!(exec.cap_drop(), &[13, 0]),
+            _ => bail!("no exec subcommand"),
+        }
+        assert!(Config::try_parse_from(&["pinns", "exec", "--cap-drop", "cap_foo", "sh"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_args_from_no_color() {
+        assert!(!Config::parse_args_from(vec!["pinns", "--no-color", "--net"]).color());
+        assert!(!Config::parse_args_from(vec!["pinns", "--net"]).no_color);
+    }
+
+    #[test]
+    fn parse_flag_success() -> Result<()> {
+        assert!(parse_flag("1")?);
+        assert!(parse_flag("true")?);
+        assert!(!parse_flag("0")?);
+        assert!(!parse_flag("false")?);
+        assert!(!parse_flag("")?);
+        assert!(parse_flag("yes").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn create_missing_dir_success_already_existing() -> Result<()> {
+        // A directory created in between the check and the creation
+        let dir = tempdir()?;
+        let path = dir.path().join("netns");
+        create_dir(&path)?;
+        create_missing_dir(&path, false)?;
+        is_dir_or_create(&path, false)?;
+
+        let file = dir.path().join("file");
+        File::create(&file)?;
+        assert!(is_dir_or_create(&file, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_dirs_failed_not_existing_path() -> Result<()> {
+        let mut c = Config::default();
+        c.dir = PathBuf::from("/not/existing/path");
+        c.cgroup = true;
+        c.validate()?;
+        assert!(c.prepare_dirs().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_path_not_dir() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let mut c = Config::default();
+        c.dir = file.path().into();
+        c.cgroup = true;
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_failed_parent_dir_file() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = Config::default();
+        c.dir = dir.path().into();
+        c.uts = true;
+        File::create(c.parent_dir_for_namespace("uts"))?;
+        assert!(c.validate().is_err());
+        Ok(())
+    }
+
+    fn validate_subcommand(args: &[&str]) -> Result<()> {
+        match Config::try_parse_from(args)?.subcommand() {
+            Some(subcommand) => subcommand.validate(),
+            None => bail!("no subcommand"),
+        }
+    }
+
+    #[test]
+    fn subcommand_validate_list_without_namespaces() -> Result<()> {
+        let dir = tempdir()?;
+        let dir = dir.path().display().to_string();
+        validate_subcommand(&["pinns", "list", "-d", &dir])?;
+        validate_subcommand(&["pinns", "gc", "-d", &dir])?;
+        validate_subcommand(&["pinns", "selftest", "-d", &dir])?;
+        validate_subcommand(&["pinns", "config-schema"])
+    }
+
+    #[test]
+    fn subcommand_validate_failed_dir_not_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let dir = dir.path().join("missing").display().to_string();
+        assert!(validate_subcommand(&["pinns", "list", "-d", &dir]).is_err());
+        assert!(validate_subcommand(&["pinns", "gc", "-d", &dir]).is_err());
+        assert!(validate_subcommand(&["pinns", "selftest", "-d", &dir]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn subcommand_validate_unpin() -> Result<()> {
+        validate_subcommand(&["pinns", "unpin", "-f", "pinned", "net"])?;
+        assert!(validate_subcommand(&["pinns", "unpin", "-f", "../pinned", "net"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn subcommand_validate_serve() -> Result<()> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("pinns.sock").display().to_string();
+        validate_subcommand(&["pinns", "serve", "--socket", &socket])?;
+
+        File::create(&socket)?;
+        assert!(validate_subcommand(&["pinns", "serve", "--socket", &socket]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn subcommand_validate_exec_pid_file() -> Result<()> {
+        let dir = tempdir()?;
+        let pid_file = dir.path().join("pid").display().to_string();
+        validate_subcommand(&["pinns", "exec", "--pid-file", &pid_file, "sh"])?;
+
+        let pid_file = dir.path().join("missing").join("pid").display().to_string();
+        assert!(validate_subcommand(&["pinns", "exec", "--pid-file", &pid_file, "sh"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remount_flags() -> Result<()> {
+        assert!(Config::try_parse_from(&["pinns"])?.remount_flags().is_empty());
+        let args = &["pinns", "--mount-flags", "nosuid,noexec", "--read-only"];
+        let c = Config::try_parse_from(args)?;
+        assert_eq!(
+            c.remount_flags(),
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_RDONLY
+        );
+        assert!(Config::try_parse_from(&["pinns", "--mount-flags", "nosymfollow"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_config() -> Result<()> {
+        let args = &["pinns", "--net", "-u", "--mount-flags", "nodev", "--file-mode", "0600"];
+        let mut c = Config::try_parse_from(args)?;
+        c.validate()?;
+        let value = serde_json::to_value(&c)?;
+        assert_eq!(value["namespaces"], serde_json::json!(["net", "uts"]));
+        assert_eq!(value["mount_flags"], serde_json::json!(["nodev"]));
+        assert_eq!(value["file_mode"], "0600");
+        assert_eq!(value["dir_mode"], "0755");
+        assert_eq!(value["propagation"], "unchanged");
+        assert!(value.get("subcommand").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn shell_generate() -> Result<()> {
+        for shell in &["bash", "elvish", "fish", "powershell", "zsh"] {
+            let mut buf = vec![];
+            shell.parse::<Shell>()?.generate(&mut buf);
+            let script = String::from_utf8(buf)?;
+            assert!(script.contains("pinns"));
+            assert!(script.contains("mount-flags"));
+        }
+        assert!("tcsh".parse::<Shell>().is_err());
         Ok(())
     }
 }
@@ -0,0 +1,66 @@
+//! Error related structures
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The classes of failures, each of them resulting in a distinct exit code
+pub enum Failure {
+    /// The provided configuration is invalid
+    Config,
+
+    /// The namespaces could not be created or joined
+    Unshare,
+
+    /// The namespaces could not be mounted or unmounted
+    Mount,
+}
+
+impl Failure {
+    /// The exit code of the process for the failure
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Config => 2,
+            Failure::Unshare => 3,
+            Failure::Mount => 4,
+        }
+    }
+
+    /// Retrieve the exit code for the error, which defaults to 1 for
+    /// unclassified ones
+    pub fn exit_code_for(err: &anyhow::Error) -> i32 {
+        err.downcast_ref::<Failure>().map_or(1, |x| x.exit_code())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Failure::Config => "invalid configuration",
+            Failure::Unshare => "unable to create namespaces",
+            Failure::Mount => "unable to mount namespaces",
+        };
+        write!(f, "{} (exit code {})", msg, self.exit_code())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use anyhow::{format_err, Context, Result};
+
+    #[test]
+    fn exit_code_for_failure() {
+        let res: Result<()> = Err(format_err!("inner"));
+        let err = res.context(Failure::Mount).context("outer").unwrap_err();
+        assert_eq!(Failure::exit_code_for(&err), 4);
+        assert_eq!(err.to_string(), "outer");
+        assert_eq!(
+            err.chain().nth(1).map(|x| x.to_string()),
+            Some("unable to mount namespaces (exit code 4)".into())
+        );
+    }
+
+    #[test]
+    fn exit_code_for_unclassified() {
+        assert_eq!(Failure::exit_code_for(&format_err!("error")), 1);
+    }
+}
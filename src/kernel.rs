@@ -0,0 +1,86 @@
+//! Detection and usage of optional features of the running kernel
+use nix::{errno::Errno, fcntl::OFlag, unistd::close};
+use std::{
+    ffi::CString,
+    fs::read_to_string,
+    os::unix::{ffi::OsStrExt, io::RawFd},
+    path::Path,
+};
+
+/// The openat2(2) system call number, which is the same on all architectures
+const SYS_OPENAT2: libc::c_long = 437;
+
+/// The openat2(2) resolve flags to refuse all kinds of symlinks
+pub const RESOLVE_NO_SYMLINKS: u64 = 0x02 | 0x04;
+
+/// The openat2(2) resolve flag to stay below the directory descriptor
+pub const RESOLVE_BENEATH: u64 = 0x08;
+
+#[repr(C)]
+/// The `open_how` argument of openat2(2)
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Open the path via openat2(2) relative to the directory descriptor
+pub fn openat2(dir_fd: RawFd, path: &Path, flags: OFlag, resolve: u64) -> nix::Result<RawFd> {
+    let how = OpenHow {
+        flags: flags.bits() as u64,
+        mode: 0,
+        resolve,
+    };
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| nix::Error::InvalidPath)?;
+    let res = unsafe {
+        libc::syscall(
+            SYS_OPENAT2,
+            dir_fd,
+            path.as_ptr(),
+            &how as *const OpenHow,
+            std::mem::size_of::<OpenHow>(),
+        )
+    };
+    Errno::result(res).map(|fd| fd as RawFd)
+}
+
+/// Whether the running kernel provides openat2(2), available since Linux 5.6
+pub fn openat2_supported() -> bool {
+    let flags = OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+    match openat2(libc::AT_FDCWD, Path::new("/"), flags, 0) {
+        Ok(fd) => {
+            let _ = close(fd);
+            true
+        }
+        Err(e) => e.as_errno() != Some(Errno::ENOSYS),
+    }
+}
+
+/// Whether user namespaces are disabled via `max_user_namespaces` below the
+/// proc path
+pub fn user_namespaces_disabled(proc_path: &Path) -> bool {
+    read_to_string(proc_path.join("sys/user/max_user_namespaces"))
+        .map_or(false, |x| x.trim() == "0")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn user_namespaces_disabled_success() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(!user_namespaces_disabled(dir.path()));
+
+        create_dir_all(dir.path().join("sys/user"))?;
+        let path = dir.path().join("sys/user/max_user_namespaces");
+        write(&path, "0\n")?;
+        assert!(user_namespaces_disabled(dir.path()));
+        write(&path, "15000\n")?;
+        assert!(!user_namespaces_disabled(dir.path()));
+        Ok(())
+    }
+}
@@ -3,92 +3,2595 @@
 //!
 //! A simple utility to pin Linux namespaces
 
+#[cfg(test)]
+/// Return early from a test which has to run as root
+macro_rules! skip_unless_root {
+    () => {
+        if !nix::unistd::Uid::effective().is_root() {
+            return Ok(());
+        }
+    };
+}
+
+mod capability;
 mod config;
+mod error;
+mod kernel;
+mod mountinfo;
+mod oci;
+mod output;
+mod selinux;
+mod server;
+mod signal;
+mod socket;
+mod status;
+
+pub use config::{Config, ConfigBuilder};
+pub use error::Failure;
+pub use output::{PinnedInodes, PinnedPaths};
 
-use config::{Config, Namespace};
+use config::{
+    cgroup_v2_host, config_file_schema, namespace_supported, parent_dir_for_namespace, Exec, Gc,
+    IdMapping, List, LogFormat, Namespace, Selftest, Serve, SubCommand, Unpin, NAMESPACE_NAMES,
+};
+use output::{Index, ListEntry, Metadata, OutputFormat, INDEX_FILE};
 
-use anyhow::{Context, Result};
-use env_logger::try_init;
-use log::debug;
+use anyhow::{bail, format_err, Context, Error, Result};
+use env_logger::{Builder, Target, WriteStyle};
+use log::{debug, error, info, max_level, warn, LevelFilter};
 use nix::{
-    fcntl::{open, OFlag},
-    mount::{mount, MsFlags},
-    sched::{unshare, CloneFlags},
-    sys::stat::Mode,
-    unistd::close,
+    errno::Errno,
+    fcntl::{open, openat, OFlag},
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        signal::{kill, Signal},
+        socket::accept,
+        stat::{fchmod, stat, umask, Mode},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{chown, close, execvp, fork, getpid, pipe2, sethostname, ForkResult, Gid, Pid, Uid},
+};
+use std::{
+    env::current_dir,
+    ffi::CString,
+    fs::{
+        canonicalize, read_dir, read_to_string, remove_dir, remove_dir_all, remove_file, write,
+        File, OpenOptions,
+    },
+    io::{stdout, ErrorKind, Read, Write},
+    mem::take,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Component, Path, PathBuf},
+    process::exit,
+    thread::sleep,
+    time::{Duration, Instant},
 };
-use std::{env::set_var, path::PathBuf};
 
 /// The main entry point for pinns
 #[derive(Default)]
 pub struct Pinns {
     config: Config,
+
+    // File descriptor of the newly created mount namespace, if requested
+    mnt_fd: Option<RawFd>,
+
+    // File descriptors of the other namespaces unshared by the holder process,
+    // which exits after unsharing them
+    namespace_fds: Vec<(Namespace, RawFd)>,
+
+    // Exit code of the executed command
+    exit_code: i32,
+
+    // Bind mount targets created by this run, which get removed on signals
+    targets: Vec<BindTarget>,
+
+    // Whether the tmpfs at the parent directory got mounted by this run
+    tmpfs_mounted: bool,
+
+    // Namespaces pinned by this run, which get returned by `run_and_collect`
+    pinned: PinnedPaths,
+}
+
+/// The log target of the timing records, whose fields are structured in JSON
+/// log messages
+const TIMING_TARGET: &str = "pinns::timing";
+
+/// The keyctl(2) operation to join or create a session keyring
+const KEYCTL_JOIN_SESSION_KEYRING: libc::c_int = 1;
+
+/// How often `serve` checks for finished workers once the idle timeout passed
+const WORKER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A bind mount target created during pinning
+struct BindTarget {
+    namespace: &'static str,
+    path: PathBuf,
+    mounted: bool,
 }
 
 impl Pinns {
-    /// Run pinns with the provided CLI configuration
+    /// Create a new instance from the provided configuration
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Run pinns with the provided configuration
     pub fn run(&mut self) -> Result<()> {
+        if self.config.version_json() {
+            output::print_version();
+            return Ok(());
+        }
+
+        // The environment and config file may contain the log settings,
+        // whereas their errors should be logged as well
+        let merged = self
+            .config
+            .merge_env()
+            .and_then(|_| self.config.merge_config_file());
         self.init_logging()?;
-        self.config.validate()?;
-        self.unshare()?;
-        self.bind_namespaces()
+        merged.context(Failure::Config)?;
+        log_kernel_support(self.config.proc_path());
+        if let Some(subcommand) = self.config.subcommand() {
+            subcommand.validate().context(Failure::Config)?;
+        }
+        if self.config.print_config() {
+            return self.print_config();
+        }
+
+        match self.config.subcommand() {
+            Some(SubCommand::Exec(exec)) => {
+                let exec = exec.clone();
+                signal::install()?;
+                self.pin()?;
+                if self.config.dry_run() {
+                    println!("execute {}", exec.command().join(" "));
+                    return Ok(());
+                }
+                self.exec(&exec)
+            }
+            Some(SubCommand::Completions(completions)) => {
+                completions.shell().generate(&mut stdout());
+                Ok(())
+            }
+            Some(SubCommand::ConfigSchema(_)) => {
+                println!("{}", config_file_schema()?);
+                Ok(())
+            }
+            Some(SubCommand::Gc(gc)) => gc_pins(gc.dir(), gc.dir_suffix(), gc.keep_dirs()),
+            Some(SubCommand::List(list)) => self.list(list),
+            Some(SubCommand::Selftest(selftest)) => self.selftest(selftest),
+            Some(SubCommand::Serve(serve)) => {
+                signal::install()?;
+                self.serve(serve)
+            }
+            Some(SubCommand::Unpin(unpin)) => self.unpin(unpin).context(Failure::Mount),
+            None => {
+                signal::install()?;
+                self.pin()
+            }
+        }
+    }
+
+    /// Run pinns like `run`, returning the namespaces pinned by it mapped to
+    /// their bind paths
+    ///
+    /// The map is empty for dry runs, if pinning is disabled and for
+    /// subcommands which do not pin.
+    pub fn run_and_collect(&mut self) -> Result<PinnedPaths> {
+        self.run()?;
+        Ok(take(&mut self.pinned))
+    }
+
+    /// Print the effective configuration as JSON to stdout
+    ///
+    /// The namespaces get resolved by validating the configuration, unless the
+    /// subcommand does not pin any.
+    fn print_config(&mut self) -> Result<()> {
+        match self.config.subcommand() {
+            None | Some(SubCommand::Exec(_)) => self.config.validate().context(Failure::Config)?,
+            _ => {}
+        }
+        let config = serde_json::to_string_pretty(&self.config)
+            .context("unable to serialize the configuration")?;
+        println!("{}", config);
+        Ok(())
+    }
+
+    /// Report a failure of `run` to stderr
+    ///
+    /// The error chain gets serialized as JSON if it is the output format,
+    /// otherwise it is logged.
+    pub fn report_error(&self, err: &Error) {
+        if self.config.output_format() == OutputFormat::Json {
+            return output::print_error(err);
+        }
+
+        let msg = err
+            .chain()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+        // Errors are still reported if logging is turned off
+        if max_level() == LevelFilter::Off {
+            eprintln!("{}", msg);
+        } else {
+            error!("{}", msg);
+        }
+    }
+
+    /// The exit code of the command run via the exec subcommand
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    /// Unshare and pin the configured namespaces, printing the pinned paths
+    fn pin(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let paths = match self.pin_namespaces()? {
+            Some(paths) => paths,
+            None => return Ok(()),
+        };
+        self.log_timing("step=total", start);
+        let inodes = if self.config.include_inode() {
+            Some(namespace_inodes(&paths)?)
+        } else {
+            None
+        };
+        output::print(self.config.output_format(), &paths, inodes.as_ref())?;
+        self.pinned = paths;
+        Ok(())
+    }
+
+    /// Unshare and pin the configured namespaces
+    ///
+    /// The bind mounts are always done after unsharing and within the host
+    /// mount namespace, even if the mount namespace is pinned as well. This
+    /// way the host sees all pinned namespaces, for example when combining
+    /// `--mount` and `--net`.
+    ///
+    /// Returns the pinned paths, or nothing for dry runs and if pinning is
+    /// disabled. This is the run logic shared by pinning, `exec` and the
+    /// requests of `serve`, which only differ in what they do with the paths.
+    fn pin_namespaces(&mut self) -> Result<Option<PinnedPaths>> {
+        self.config.validate().context(Failure::Config)?;
+        if self.config.dry_run() {
+            self.dry_run()?;
+            return Ok(None);
+        }
+        if self.config.mount_tmpfs() {
+            self.config.prepare_dir().context(Failure::Config)?;
+            self.mount_tmpfs().context(Failure::Mount)?;
+        }
+
+        let created = self
+            .config
+            .prepare_dirs()
+            .and_then(|_| self.check_shared_mounts())
+            .context(Failure::Config)
+            .and_then(|_| {
+                let unshare_start = Instant::now();
+                let res = match (self.config.join_pid(), self.config.pin_pid()) {
+                    (Some(pid), _) => self.join(pid),
+                    (_, Some(_)) => Ok(()),
+                    _ => self.unshare(),
+                };
+                self.log_timing("step=unshare", unshare_start);
+                res.context(Failure::Unshare)
+            });
+        if let Err(e) = created {
+            // Nothing got pinned yet, but the tmpfs has to be removed again
+            if self.tmpfs_mounted {
+                self.cleanup(&[]);
+            }
+            return Err(e);
+        }
+        if let Some(path) = self.config.pass_fd_socket() {
+            self.pass_fds(path).context(Failure::Mount)?;
+        }
+        if self.config.no_pin() {
+            debug!("skipping to pin namespaces");
+            return Ok(None);
+        }
+
+        let paths = self.bind_namespaces().context(Failure::Mount)?;
+        let res = self
+            .label_pins(&paths)
+            .context(Failure::Mount)
+            .and_then(|_| self.record_pins(&paths));
+        if let Err(e) = res {
+            // Pins which cannot be reported would be leaked otherwise
+            self.cleanup(&self.targets);
+            return Err(e);
+        }
+        Ok(Some(paths))
+    }
+
+    /// Write the pinned paths to the configured files and the index
+    fn record_pins(&self, paths: &PinnedPaths) -> Result<()> {
+        if let Some(path) = self.config.pin_paths_file() {
+            output::write_file(path, paths)?;
+        }
+        if let Some(path) = self.config.metadata_file() {
+            Metadata::new(paths).write(path)?;
+        }
+        if self.config.index() {
+            Index::update(&self.config.dir().join(INDEX_FILE), |x| x.add(paths))?;
+        }
+        Ok(())
+    }
+
+    /// Apply the configured SELinux label to the pinned namespace files
+    ///
+    /// Labeling is skipped with a warning if SELinux is disabled.
+    fn label_pins(&self, paths: &PinnedPaths) -> Result<()> {
+        let label = match self.config.selinux_label() {
+            Some(label) => label,
+            None => return Ok(()),
+        };
+        if !selinux::is_enabled()? {
+            warn!("SELinux is disabled, not applying label {}", label);
+            return Ok(());
+        }
+        for path in paths.values() {
+            debug!("labeling {} with {}", path.display(), label);
+            selinux::set_label(path, label)?;
+        }
+        Ok(())
+    }
+
+    /// Warn about every directory to pin into which resides on a shared mount,
+    /// or refuse it in strict mode
+    ///
+    /// The bind mounts of pinned namespaces would otherwise propagate to all
+    /// peers of the mount, like the host mount namespace.
+    fn check_shared_mounts(&self) -> Result<()> {
+        if self.config.no_pin() {
+            return Ok(());
+        }
+
+        let mut dirs = vec![];
+        for ns in self.config.namespaces().into_iter().filter(|x| x.enabled()) {
+            let dir = self.config.parent_dir_for_namespace(ns.name());
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        for dir in dirs {
+            if !mountinfo::is_on_shared_mount(&dir)? {
+                continue;
+            }
+            if self.config.strict() {
+                bail!(
+                    "directory {} is on a shared mount, whose bind mounts propagate to its peers",
+                    dir.display()
+                )
+            }
+            warn!(
+                "directory {} is on a shared mount, pinned namespaces will propagate to its peers",
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Send the file descriptors of all enabled namespaces to the socket
+    fn pass_fds(&self, path: &Path) -> Result<()> {
+        let mut names = vec![];
+        let mut fds = vec![];
+        let mut res = Ok(());
+        for ns in self.config.namespaces().into_iter().filter(|x| x.enabled()) {
+            let source = self.namespace_source(&ns);
+            match open(&source, OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty()) {
+                Ok(fd) => {
+                    names.push(*ns.name());
+                    fds.push(fd);
+                }
+                Err(e) => {
+                    res = Err(e).context(format!("unable to open namespace {}", source.display()));
+                    break;
+                }
+            }
+        }
+
+        if res.is_ok() {
+            debug!("passing namespace file descriptors to {}", path.display());
+            res = socket::send_fds(path, &names, &fds);
+        }
+        for fd in fds {
+            close(fd).context("unable to close file descriptor")?;
+        }
+        res
+    }
+
+    /// Log the duration since the start of a step if requested
+    fn log_timing(&self, fields: &str, start: Instant) {
+        if self.config.timing() {
+            let duration = start.elapsed().as_micros();
+            info!(target: TIMING_TARGET, "{} duration_us={}", fields, duration);
+        }
+    }
+
+    /// Print the actions of pinning without performing them
+    fn dry_run(&self) -> Result<()> {
+        let namespaces = self
+            .config
+            .namespaces()
+            .into_iter()
+            .filter(|x| x.enabled())
+            .collect::<Vec<_>>();
+        let names = namespaces
+            .iter()
+            .map(|x| *x.name())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match (self.config.join_pid(), self.config.pin_pid()) {
+            (Some(pid), _) => println!("join namespaces of process {}: {}", pid, names),
+            (_, Some(pid)) => println!("use namespaces of process {}: {}", pid, names),
+            _ => {
+                let flags = namespaces
+                    .iter()
+                    .filter(|x| self.config.enters_namespace(x))
+                    .fold(CloneFlags::empty(), |flags, ns| flags | ns.clone_flag());
+                println!("unshare flags {:#x}: {}", flags.bits(), names)
+            }
+        }
+
+        if self.config.no_pin() {
+            return Ok(());
+        }
+        for ns in &namespaces {
+            let source = match self.config.join_pid() {
+                Some(pid) if self.config.enters_namespace(ns) => {
+                    self.config.proc_namespace(&pid.to_string(), ns.name())
+                }
+                _ => self.config.namespace_source(ns),
+            };
+            println!(
+                "bind mount {} to {}",
+                source.display(),
+                self.config.bind_path_for_namespace(ns.name()).display()
+            );
+        }
+        Ok(())
     }
 
     // Setup logging via env logger
     fn init_logging(&self) -> Result<()> {
-        set_var("RUST_LOG", format!("pinns={}", self.config.log_level()));
-        try_init().context("unable to init logger")
+        let mut builder = Builder::new();
+        builder.filter_module("pinns", self.config.log_level());
+        if !self.config.color() {
+            builder.write_style(WriteStyle::Never);
+        }
+        // Falling back to stderr gets logged after initializing the logger
+        let mut log_file_err = None;
+        if let Some(path) = self.config.log_file() {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => {
+                    builder.target(Target::Pipe(Box::new(file)));
+                    builder.write_style(WriteStyle::Never);
+                }
+                Err(e) => log_file_err = Some((path, e)),
+            }
+        }
+        if self.config.log_format() == LogFormat::Json {
+            builder.format(|buf, record| {
+                let message = record.args().to_string();
+                let mut line = serde_json::json!({
+                    "level": record.level().to_string(),
+                    "message": message,
+                    "timestamp": buf.timestamp().to_string(),
+                });
+                if record.target() == TIMING_TARGET {
+                    add_fields(&mut line, &message);
+                }
+                writeln!(buf, "{}", line)
+            });
+        }
+        // Embedding applications may have set up their own logger already
+        if builder.try_init().is_err() {
+            debug!("using the already initialized logger");
+        }
+        if let Some((path, e)) = log_file_err {
+            warn!(
+                "unable to open log file {}, logging to stderr instead: {}",
+                path.display(),
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Execute the command in a child process within the new namespaces
+    ///
+    /// The command runs as PID 1 if the PID namespace has been unshared.
+    fn exec(&mut self, exec: &Exec) -> Result<()> {
+        let args = exec
+            .command()
+            .iter()
+            .map(|x| CString::new(x.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("command contains a nul byte")?;
+
+        debug!("executing {}", exec.command().join(" "));
+        match fork().context("unable to fork process")? {
+            ForkResult::Child => {
+                if let Some(score) = exec.oom_score_adj() {
+                    let path = self.config.proc_path().join("self/oom_score_adj");
+                    if let Err(e) = write(&path, score.to_string()) {
+                        error!("unable to write {}: {}", path.display(), e);
+                        exit(127);
+                    }
+                }
+
+                // Enter the pinned mount namespace, the parent is back in the
+                // one of the host
+                if let Some(fd) = self.mnt_fd {
+                    if let Err(e) = setns(fd, CloneFlags::CLONE_NEWNS) {
+                        error!("unable to enter mount namespace: {}", e);
+                        exit(127);
+                    }
+                }
+                self.enter_holder_namespaces();
+                if let Err(e) = capability::drop_capabilities(exec.cap_drop()) {
+                    error!("unable to drop capabilities: {:#}", e);
+                    exit(127);
+                }
+                if let Err(e) = execvp(&args[0], &args) {
+                    error!("unable to execute {}: {}", exec.command()[0], e);
+                }
+                exit(127)
+            }
+            ForkResult::Parent { child } => {
+                if let Some(path) = exec.pid_file() {
+                    debug!("writing PID {} to {}", child, path.display());
+                    if let Err(e) = output::write_atomic(path, child.to_string().as_bytes()) {
+                        // The command must not keep running unsupervised
+                        if kill(child, Signal::SIGKILL).is_ok() {
+                            let _ = waitpid(child, None);
+                        }
+                        return Err(e);
+                    }
+                }
+
+                let res = self.wait(child);
+                if let Some(path) = exec.pid_file() {
+                    if let Err(e) = remove_file(path) {
+                        warn!("unable to remove {}: {}", path.display(), e);
+                    }
+                }
+                res?;
+
+                if let Some(signal) = signal::received() {
+                    self.cleanup(&self.targets);
+                    // Stopping is the regular way to end waiting
+                    if exec.wait() {
+                        info!("unpinned namespaces after {:?}", signal);
+                        self.pinned.clear();
+                        return Ok(());
+                    }
+                    bail!("interrupted by {:?}", signal)
+                }
+
+                if exec.wait() {
+                    info!("waiting for SIGINT or SIGTERM to unpin the namespaces");
+                    let signal = signal::wait()?;
+                    self.cleanup(&self.targets);
+                    info!("unpinned namespaces after {:?}", signal);
+                    self.pinned.clear();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Enter the namespaces unshared by the holder process within the child
+    /// of `exec`
+    ///
+    /// New PID and time namespaces only apply to the children of the process,
+    /// which is why the child forks once more and waits for the command.
+    fn enter_holder_namespaces(&mut self) {
+        if self.namespace_fds.is_empty() {
+            return;
+        }
+        if let Err(e) = setns_all("the holder process", &self.namespace_fds) {
+            error!("{:#}", e);
+            exit(127);
+        }
+        if !self.namespace_fds.iter().any(|(ns, _)| ["pid", "time"].contains(ns.name())) {
+            return;
+        }
+        match fork() {
+            Ok(ForkResult::Child) => {}
+            Ok(ForkResult::Parent { child }) => match self.wait(child) {
+                Ok(()) => exit(self.exit_code),
+                Err(e) => {
+                    error!("{:#}", e);
+                    exit(127)
+                }
+            },
+            Err(e) => {
+                error!("unable to fork process: {}", e);
+                exit(127)
+            }
+        }
+    }
+
+    /// Wait for the child process to exit and remember its exit code
+    ///
+    /// Received termination signals get forwarded to the child.
+    fn wait(&mut self, child: Pid) -> Result<()> {
+        let mut forwarded = false;
+        loop {
+            if let (Some(signal), false) = (signal::received(), forwarded) {
+                debug!("forwarding {:?} to command", signal);
+                if let Err(e) = kill(child, signal) {
+                    warn!("unable to forward {:?} to command: {}", signal, e);
+                }
+                forwarded = true;
+            }
+            match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => {
+                    debug!("command exited with {}", code);
+                    self.exit_code = code;
+                    return Ok(());
+                }
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    debug!("command got terminated by {:?}", signal);
+                    self.exit_code = 128 + signal as i32;
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => {}
+                Err(e) => return Err(e).context("unable to wait for child process"),
+            }
+        }
+    }
+
+    /// List all pinned namespaces below the directory
+    fn list(&self, list: &List) -> Result<()> {
+        let entries = pinned_namespaces(list.dir(), list.dir_suffix())?;
+        output::print_list(self.config.output_format(), &entries)
+    }
+
+    /// Pin a throwaway UTS namespace into a temporary directory, verify its
+    /// bind mount and unpin it again
+    ///
+    /// The temporary directory gets removed even if a step fails.
+    fn selftest(&self, selftest: &Selftest) -> Result<()> {
+        let dir = selftest.dir().join(format!("pinns-selftest-{}", getpid()));
+        let config = Config::builder()
+            .dir(&dir)
+            .filename("selftest")
+            .namespace("uts")?
+            .mkdir(true)
+            .build();
+        let bind_path = config.bind_path_for_namespace("uts");
+
+        let res = Pinns::new(config).pin().and_then(|_| {
+            if !mountinfo::is_mountpoint(&bind_path)? {
+                bail!("{} is not a mountpoint", bind_path.display())
+            }
+            umount2(&bind_path, MntFlags::MNT_DETACH)
+                .context(format!("unable to unmount {}", bind_path.display()))
+        });
+
+        // Clean up after a failure in between pinning and unpinning
+        if mountinfo::is_mountpoint(&bind_path).unwrap_or(false) {
+            if let Err(e) = umount2(&bind_path, MntFlags::MNT_DETACH) {
+                warn!("unable to unmount {}: {}", bind_path.display(), e)
+            }
+        }
+        if dir.exists() {
+            if let Err(e) = remove_dir_all(&dir) {
+                warn!("unable to remove {}: {}", dir.display(), e)
+            }
+        }
+
+        res.context("selftest failed")?;
+        info!("selftest succeeded");
+        Ok(())
+    }
+
+    /// Answer pin requests on the unix socket until a termination signal
+    /// gets received
+    ///
+    /// Every request is served by a forked worker, because unsharing changes
+    /// the namespaces of the calling process for good.
+    fn serve(&self, serve: &Serve) -> Result<()> {
+        let socket = serve.socket();
+        // Everyone who may connect gets to pin namespaces as root, so the
+        // socket must not be created with looser permissions than requested
+        let previous = umask(Mode::from_bits_truncate(!serve.socket_mode() & 0o777));
+        let listener = UnixListener::bind(socket);
+        umask(previous);
+        let listener = listener.context(format!("unable to listen at {}", socket.display()))?;
+        info!("listening at {}", socket.display());
+
+        let idle_timeout = Duration::from_secs(serve.idle_timeout());
+        let mut last_request = Instant::now();
+        let mut workers = 0;
+        let res = loop {
+            if let Some(signal) = signal::received() {
+                info!("stopping on {:?}", signal);
+                break Ok(());
+            }
+            workers -= reap_workers();
+
+            if serve.idle_timeout() > 0 {
+                let idle = last_request.elapsed();
+                let timeout = if idle < idle_timeout {
+                    idle_timeout - idle
+                } else if workers == 0 {
+                    info!("stopping after being idle for {}s", serve.idle_timeout());
+                    break Ok(());
+                } else {
+                    // In-flight requests must not be cut off, so the workers
+                    // get checked shortly until all of them are done
+                    WORKER_POLL_INTERVAL
+                };
+                let timeout = timeout.as_millis().min(i32::max_value() as u128);
+                let mut fds = [PollFd::new(listener.as_raw_fd(), PollFlags::POLLIN)];
+                match poll(&mut fds, timeout as libc::c_int) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+                    Err(e) => break Err(e).context("unable to wait for connections"),
+                }
+            }
+
+            // The standard library retries on EINTR, which would defer the
+            // shutdown until the next connection
+            let fd = match accept(listener.as_raw_fd()) {
+                Ok(fd) => fd,
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+                Err(e) => break Err(e).context("unable to accept connection"),
+            };
+            last_request = Instant::now();
+            let stream = unsafe { UnixStream::from_raw_fd(fd) };
+            match fork() {
+                Ok(ForkResult::Child) => exit(serve_request(&self.config, serve, &stream)),
+                Ok(ForkResult::Parent { child }) => {
+                    debug!("serving request in {}", child);
+                    workers += 1;
+                }
+                Err(e) => error!("unable to fork worker: {}", e),
+            }
+        };
+
+        if let Err(e) = remove_file(socket) {
+            warn!("unable to remove {}: {}", socket.display(), e)
+        }
+        res
+    }
+
+    /// Unmount and remove the pinned namespaces, ignoring already unpinned ones
+    fn unpin(&self, unpin: &Unpin) -> Result<()> {
+        let mut paths: Vec<(String, PathBuf)> = match unpin.metadata_file() {
+            Some(path) => Metadata::read(path)?.paths.into_iter().collect(),
+            None => vec![],
+        };
+        paths.extend(
+            unpin
+                .namespaces()
+                .iter()
+                .map(|x| (x.clone(), unpin.bind_path_for_namespace(x))),
+        );
+
+        for (name, path) in &paths {
+            if !path.exists() {
+                debug!("{} does not exist", path.display());
+                continue;
+            }
+
+            remove_pin(path, unpin.umount_flags())?;
+            info!("unpinned {}", path.display());
+
+            // Only the per namespace subdirectory gets removed, not the parent
+            // directory of a flat pin
+            let ns_dir_name = format!("{}{}", name, unpin.dir_suffix());
+            if !unpin.keep_dirs() {
+                if let Some(ns_dir) = path.parent().filter(|x| x.ends_with(&ns_dir_name)) {
+                    remove_dir_if_empty(ns_dir)?;
+                }
+            }
+        }
+
+        let dir = unpin.dir();
+        let index_path = dir.join(INDEX_FILE);
+        if index_path.exists() {
+            let paths = paths.into_iter().map(|(_, x)| x).collect::<Vec<_>>();
+            Index::update(&index_path, |x| x.remove(&paths))?;
+        }
+
+        if unpin.mount_tmpfs() {
+            if !mountinfo::is_mountpoint(dir)? {
+                debug!("no tmpfs mounted at {}", dir.display());
+            } else if pinned_namespaces(dir, unpin.dir_suffix())?.iter().any(|x| x.active) {
+                info!("keeping tmpfs at {}, which still contains pins", dir.display());
+            } else {
+                umount2(dir, unpin.umount_flags())
+                    .context(format!("unable to unmount tmpfs at {}", dir.display()))?;
+                info!("unmounted tmpfs at {}", dir.display());
+            }
+        }
+
+        if let Some(path) = unpin.metadata_file() {
+            remove_file(path).context(format!("unable to remove metadata {}", path.display()))?;
+        }
+        Ok(())
     }
 
     /// Unshare the configured namespaces
-    fn unshare(&self) -> Result<()> {
-        let flags = self
+    ///
+    /// If the mount namespace gets unshared, then the process re-enters the
+    /// original mount namespace afterwards. This ensures that all bind mounts
+    /// are visible to the host rather than being hidden in the new namespace.
+    ///
+    /// A new user namespace gets unshared by a holder process instead, see
+    /// `unshare_in_holder`.
+    fn unshare(&mut self) -> Result<()> {
+        if self.config.enters_namespace(self.config.namespaces().cgroup()) {
+            if let Some(path) = self.config.cgroup_path() {
+                move_to_cgroup(path)?;
+            }
+            self.log_cgroup_root();
+        }
+
+        if self.config.enters_namespace(self.config.namespaces().user()) {
+            self.unshare_in_holder()?;
+        } else {
+            let host_mnt_fd = if self.config.enters_namespace(self.config.namespaces().mnt()) {
+                Some(self.open_namespace("thread-self", "mnt")?)
+            } else {
+                None
+            };
+            self.unshare_namespaces()?;
+            if let Some(fd) = host_mnt_fd {
+                self.mnt_fd = Some(self.open_namespace("thread-self", "mnt")?);
+                debug!("re-entering host mount namespace");
+                setns(fd, CloneFlags::CLONE_NEWNS)
+                    .context("unable to re-enter host mount namespace")?;
+                close(fd).context("unable to close file descriptor")?;
+            }
+        }
+
+        if self.config.new_keyring() {
+            join_session_keyring()?;
+        }
+        Ok(())
+    }
+
+    /// Unshare the namespaces via a forked holder process, which becomes the
+    /// owner of the new user namespace
+    ///
+    /// Unsharing the user namespace drops all capabilities within the initial
+    /// one, which are still required for the bind mounts and re-entering the
+    /// host mount namespace. The holder therefore unshares all namespaces and
+    /// waits, while pinns writes the ID mappings from the outside and opens
+    /// the namespaces, so that the holder is able to exit again.
+    fn unshare_in_holder(&mut self) -> Result<()> {
+        let (mut report_read, mut report_write) = pipe()?;
+        let (mut release_read, release_write) = pipe()?;
+        debug!("forking holder process for unsharing the namespaces");
+        let holder = match fork().context("unable to fork holder process")? {
+            ForkResult::Child => {
+                drop(report_read);
+                drop(release_write);
+                let failed = match self.unshare_namespaces() {
+                    Ok(()) => false,
+                    Err(e) => {
+                        let _ = write!(report_write, "{:#}", e);
+                        true
+                    }
+                };
+                drop(report_write);
+                if !failed {
+                    // Closing the other end signals that pinns holds the
+                    // namespaces on its own
+                    let _ = release_read.read_to_end(&mut vec![]);
+                }
+                exit(failed as i32)
+            }
+            ForkResult::Parent { child } => child,
+        };
+        drop(report_write);
+        drop(release_read);
+
+        let res = self.adopt_holder_namespaces(holder, &mut report_read);
+        drop(release_write);
+        loop {
+            match waitpid(holder, None) {
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+                Err(e) => warn!("unable to wait for holder process: {}", e),
+                Ok(status) => debug!("holder process terminated: {:?}", status),
+            }
+            break res;
+        }
+    }
+
+    /// Write the ID mappings of the namespaces unshared by the holder process
+    /// and open them, once the holder reports to be done
+    fn adopt_holder_namespaces(&mut self, holder: Pid, report: &mut File) -> Result<()> {
+        let mut failure = String::new();
+        report
+            .read_to_string(&mut failure)
+            .context("unable to read the report of the holder process")?;
+        if !failure.is_empty() {
+            bail!(failure)
+        }
+
+        // The holder reports nothing if it got killed as well
+        let process = holder.to_string();
+        self.write_id_mappings(&process)?;
+        for ns in self.config.namespaces_to_unshare() {
+            let fd = self.open_namespace(&process, ns.proc_name())?;
+            if *ns.name() == "mnt" {
+                self.mnt_fd = Some(fd);
+            } else {
+                self.namespace_fds.push((ns, fd));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unshare the namespaces and configure them from within
+    ///
+    /// Namespaces are unshared one at a time, starting with the user namespace
+    /// so that the remaining ones are owned by it. A failure therefore names
+    /// the namespace which could not be unshared, while all previously
+    /// iterated namespaces are known to be unshared already.
+    fn unshare_namespaces(&self) -> Result<()> {
+        // Every namespace gets unshared on its own, because the order matters
+        for namespace in self.config.namespaces_to_unshare() {
+            debug!("unsharing {} namespace", namespace.name());
+            if let Err(e) = unshare(namespace.clone_flag()) {
+                let msg = format!("failed to unshare {} namespace", namespace.name());
+                let proc_path = self.config.proc_path();
+                let hint = status::unshare_hint(proc_path, namespace.name(), e.as_errno());
+                return Err(e).context(match hint {
+                    Some(hint) => format!("{}, likely because {}", msg, hint),
+                    None => msg,
+                });
+            }
+        }
+
+        if self.config.enters_namespace(self.config.namespaces().time()) {
+            self.write_time_offsets()?;
+        }
+
+        if let Some(hostname) = self.config.uts_hostname() {
+            debug!("setting hostname to {}", hostname);
+            sethostname(hostname).context("unable to set hostname")?;
+        }
+        Ok(())
+    }
+
+    /// Join the configured namespaces of the provided process
+    ///
+    /// The mount namespace is not entered to keep the bind mounts visible to
+    /// the host, but it gets bound via its file descriptor instead.
+    ///
+    /// All namespaces get opened upfront, because joining them changes the
+    /// view on the proc file system, and are joined in the order of
+    /// `setns_all`.
+    fn join(&mut self, pid: i32) -> Result<()> {
+        let mut fds = vec![];
+        let mut res = Ok(());
+        for ns in self.config.namespaces_to_join() {
+            match self.open_namespace(&pid.to_string(), ns.name()) {
+                Ok(fd) if ns.name() == "mnt" => self.mnt_fd = Some(fd),
+                Ok(fd) => fds.push((ns, fd)),
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+
+        res = res.and_then(|_| setns_all(&format!("process {}", pid), &fds));
+        for (_, fd) in fds {
+            close(fd).context("unable to close file descriptor")?;
+        }
+        res
+    }
+
+    /// Log the cgroup of the process, which becomes the root of the new
+    /// cgroup namespace
+    fn log_cgroup_root(&self) {
+        let path = self.config.proc_path().join("thread-self/cgroup");
+        match read_to_string(&path) {
+            Ok(content) => {
+                let version = if cgroup_v2_host() { "v2" } else { "v1" };
+                for line in content.lines() {
+                    info!("new cgroup {} namespace root: {}", version, line);
+                }
+            }
+            Err(e) => debug!("unable to read {}: {}", path.display(), e),
+        }
+    }
+
+    /// Write the configured UID and GID mappings of the new user namespace of
+    /// the process
+    fn write_id_mappings(&self, process: &str) -> Result<()> {
+        let dir = self.config.proc_path().join(process);
+        if !self.config.uid_mappings().is_empty() {
+            write_id_mapping(&dir.join("uid_map"), self.config.uid_mappings())?;
+        }
+
+        if !self.config.gid_mappings().is_empty() {
+            if !Uid::effective().is_root() {
+                // Unprivileged processes are not allowed to write the GID map
+                // without disabling setgroups(2) first
+                debug!("denying setgroups");
+                write(dir.join("setgroups"), "deny").context("unable to deny setgroups")?;
+            }
+            write_id_mapping(&dir.join("gid_map"), self.config.gid_mappings())?;
+        }
+        Ok(())
+    }
+
+    /// Write the configured clock offsets of the new time namespace
+    ///
+    /// This has to happen before any process enters the namespace, because the
+    /// kernel rejects changing the offsets afterwards.
+    fn write_time_offsets(&self) -> Result<()> {
+        let offsets = [
+            ("monotonic", self.config.time_offset_monotonic()),
+            ("boottime", self.config.time_offset_boottime()),
+        ]
+        .iter()
+        .filter_map(|(clock, offset)| offset.map(|x| format!("{} {} 0\n", clock, x)))
+        .collect::<String>();
+        if offsets.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.config.proc_path().join("self/timens_offsets");
+        debug!("writing {}", path.display());
+        write(&path, offsets).context(format!("unable to write {}", path.display()))
+    }
+
+    /// Open a read only file descriptor to the namespace of the process
+    fn open_namespace(&self, process: &str, name: &str) -> Result<RawFd> {
+        let path = self.config.proc_namespace(process, name);
+        open(&path, OFlag::O_RDONLY, Mode::empty())
+            .context(format!("unable to open namespace {}", path.display()))
+    }
+
+    /// Mount a tmpfs at the parent directory of the pinned namespaces
+    fn mount_tmpfs(&mut self) -> Result<()> {
+        let dir = self.config.dir();
+        if read_dir(dir)
+            .context(format!("unable to read {}", dir.display()))?
+            .next()
+            .is_some()
+        {
+            bail!("refusing to mount tmpfs over non-empty directory {}", dir.display())
+        }
+
+        debug!("mounting tmpfs at {}", dir.display());
+        let options = format!("mode={:o}", self.config.dir_mode());
+        mount(
+            Some("tmpfs"),
+            dir,
+            Some("tmpfs"),
+            MsFlags::MS_NODEV | MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID,
+            Some(options.as_str()),
+        )
+        .context(format!("unable to mount tmpfs at {}", dir.display()))?;
+        self.tmpfs_mounted = true;
+        Ok(())
+    }
+
+    /// Binds the namespaces if provided by the configuration
+    ///
+    /// The namespaces are bound concurrently according to `--jobs`, except
+    /// for the mount namespace, which always gets bound first. This way it
+    /// never depends on the timing whether bind mounts of the other
+    /// namespaces propagate into the pinned mount namespace.
+    ///
+    /// All created artifacts are removed again if binding a single namespace
+    /// fails.
+    fn bind_namespaces(&mut self) -> Result<PinnedPaths> {
+        let (first, rest): (Vec<_>, Vec<_>) = self
             .config
             .namespaces()
             .into_iter()
             .filter(|x| x.enabled())
-            .fold(CloneFlags::empty(), |mut flags, ns| {
-                flags |= ns.clone_flag();
-                debug!("unsharing {} namespace", ns.name());
-                flags
-            });
+            .partition(|x| *x.name() == "mnt");
 
-        unshare(flags).context("failed to unshare namespaces")
+        let mut targets = vec![];
+        let mut res = self.bind_serially(&first, &mut targets);
+        if res.is_ok() {
+            res = match self.bind_jobs(rest.len()) {
+                jobs if jobs > 1 => self.bind_concurrently(&rest, jobs, &mut targets),
+                _ => self.bind_serially(&rest, &mut targets),
+            };
+        }
+        if let Err(e) = res {
+            self.cleanup(&targets);
+            return Err(e);
+        }
+        self.targets = targets;
+
+        let cwd = current_dir().context("unable to get current directory")?;
+        Ok(self
+            .targets
+            .iter()
+            .map(|x| (x.namespace.to_string(), cwd.join(&x.path)))
+            .collect())
     }
 
-    /// Binds the namespaces if provided by the configuration
-    fn bind_namespaces(&self) -> Result<()> {
-        for ns in self.config.namespaces().into_iter().filter(|x| x.enabled()) {
-            self.bind_namespace(ns)?;
+    /// The number of threads to bind the amount of namespaces with
+    ///
+    /// Threads cannot be created while the new PID or time namespace differs
+    /// from the current one, which requires binding them serially.
+    fn bind_jobs(&self, namespaces: usize) -> usize {
+        let jobs = match self.config.jobs() {
+            0 => namespaces,
+            jobs => jobs.min(namespaces),
+        };
+        if jobs > 1 && !self.can_spawn_threads() {
+            debug!("binding namespaces serially, because threads cannot be created");
+            return 1;
+        }
+        jobs
+    }
+
+    /// Whether the thread is still within the PID and time namespaces of its
+    /// children, which is required for creating threads
+    ///
+    /// The kernel refuses to create threads with EINVAL after unsharing the
+    /// PID namespace, and since Linux 5.6 after unsharing the time namespace
+    /// as well, because threads cannot live in other namespaces than their
+    /// thread group.
+    fn can_spawn_threads(&self) -> bool {
+        ["pid", "time"].iter().all(|name| {
+            let children = format!("{}_for_children", name);
+            let own = stat(&self.config.proc_namespace("thread-self", name));
+            let children = stat(&self.config.proc_namespace("thread-self", &children));
+            match (own, children) {
+                (Ok(own), Ok(children)) => own.st_ino == children.st_ino,
+                // Unsupported namespaces cannot differ
+                _ => true,
+            }
+        })
+    }
+
+    /// Bind the namespaces one after another
+    fn bind_serially(&self, namespaces: &[Namespace], targets: &mut Vec<BindTarget>) -> Result<()> {
+        for ns in namespaces {
+            let start = Instant::now();
+            self.bind_namespace(*ns, targets)?;
+            self.log_timing(&format!("step=bind namespace={}", ns.name()), start);
+            if let Some(signal) = signal::received() {
+                bail!("interrupted by {:?}", signal)
+            }
         }
         Ok(())
     }
 
-    /// Bind a single namespace
-    fn bind_namespace(&self, namespace: Namespace) -> Result<()> {
-        let bind_path = self.config.parent_dir_for_namespace(namespace.name())
-                            .join(self.config.filename());
-        debug!("binding namespace: {}", bind_path.display());
+    /// Bind the namespaces using up to `jobs` threads at once
+    ///
+    /// The targets of all threads get collected, even if binding failed. The
+    /// first failure is returned, whereas further ones only get logged.
+    /// Namespaces for which no thread can be spawned get bound serially.
+    fn bind_concurrently(
+        &self,
+        namespaces: &[Namespace],
+        jobs: usize,
+        targets: &mut Vec<BindTarget>,
+    ) -> Result<()> {
+        let mut failures = vec![];
+        for chunk in namespaces.chunks(jobs) {
+            let (results, unspawned) = crossbeam_utils::thread::scope(|s| {
+                let mut handles = vec![];
+                let mut unspawned = vec![];
+                for ns in chunk {
+                    let spawned = s.builder().spawn(move |_| {
+                        let start = Instant::now();
+                        let mut targets = vec![];
+                        let res = self.bind_namespace(*ns, &mut targets);
+                        self.log_timing(&format!("step=bind namespace={}", ns.name()), start);
+                        (*ns.name(), res, targets)
+                    });
+                    match spawned {
+                        Ok(handle) => handles.push(handle),
+                        Err(e) => {
+                            warn!("unable to spawn thread for {} namespace: {}", ns.name(), e);
+                            unspawned.push(*ns);
+                        }
+                    }
+                }
+                let results = handles.into_iter().map(|x| x.join()).collect::<Vec<_>>();
+                (results, unspawned)
+            })
+            .map_err(|_| format_err!("binding namespaces panicked"))?;
 
-        let fd = open(
-            &bind_path,
-            OFlag::O_RDONLY | OFlag::O_CREAT | OFlag::O_EXCL,
-            Mode::empty(),
-        )
-        .context(format!(
-            "unable to create namespace file {}",
-            bind_path.display()
-        ))?;
-        close(fd).context("unable to close file descriptor")?;
+            for result in results {
+                let (name, res, mut bound) =
+                    result.map_err(|_| format_err!("binding namespace panicked"))?;
+                targets.append(&mut bound);
+                if let Err(e) = res {
+                    failures.push(e.context(format!("unable to bind {} namespace", name)));
+                }
+            }
+            if failures.is_empty() {
+                if let Err(e) = self.bind_serially(&unspawned, targets) {
+                    failures.push(e);
+                }
+            }
+            if !failures.is_empty() {
+                break;
+            }
+            if let Some(signal) = signal::received() {
+                bail!("interrupted by {:?}", signal)
+            }
+        }
+
+        let mut failures = failures.into_iter();
+        match failures.next() {
+            Some(e) => {
+                for other in failures {
+                    warn!("{:#}", other);
+                }
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Unmount and remove the provided targets as well as the created directories
+    fn cleanup(&self, targets: &[BindTarget]) {
+        debug!("cleaning up after failure");
+        for target in targets.iter().rev() {
+            if target.mounted {
+                if let Err(e) = umount2(&target.path, MntFlags::MNT_DETACH) {
+                    warn!("unable to unmount {}: {}", target.path.display(), e);
+                }
+            }
+            if let Err(e) = remove_file(&target.path) {
+                warn!("unable to remove {}: {}", target.path.display(), e);
+            }
+        }
 
-        let ns_path = PathBuf::from("/proc/self/ns").join(namespace.name());
+        if self.tmpfs_mounted {
+            let dir = self.config.dir();
+            if let Err(e) = umount2(dir, MntFlags::MNT_DETACH) {
+                warn!("unable to unmount tmpfs at {}: {}", dir.display(), e);
+            }
+        }
+
+        for dir in self.config.created_dirs().iter().rev() {
+            // Directories within the tmpfs are gone already
+            if !dir.exists() {
+                continue;
+            }
+            if let Err(e) = remove_dir(dir) {
+                warn!("unable to remove {}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    /// Bind mount the source to the target, retrying on transient failures
+    fn bind_mount(&self, source: &Path, target: &Path) -> nix::Result<()> {
+        let mut retries = self.config.mount_retries();
+        let mut delay = Duration::from_millis(self.config.mount_retry_delay());
+        loop {
+            match mount::<_, _, Path, Path>(Some(source), target, None, MsFlags::MS_BIND, None) {
+                Err(e) if retries > 0 && is_retryable(&e) => {
+                    warn!("mounting {} failed, retrying in {:?}: {}", target.display(), delay, e);
+                    sleep(delay);
+                    delay *= 2;
+                    retries -= 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// The path of the namespace to be bind mounted or passed on
+    ///
+    /// Namespaces which are only referred to by a file descriptor of pinns
+    /// are bound via the descriptor.
+    fn namespace_source(&self, namespace: &Namespace) -> PathBuf {
+        let fd = match self.mnt_fd {
+            Some(fd) if *namespace.name() == "mnt" => Some(fd),
+            _ => self
+                .namespace_fds
+                .iter()
+                .find(|(x, _)| x.name() == namespace.name())
+                .map(|(_, fd)| *fd),
+        };
+        match fd {
+            Some(fd) => self.config.proc_path().join("self/fd").join(fd.to_string()),
+            None => self.config.namespace_source(namespace),
+        }
+    }
+
+    /// Change the owner of the created target and bind mount the namespace
+    /// to it
+    fn mount_target(&self, namespace: &Namespace, target: &Path) -> Result<()> {
+        if let (Some(uid), Some(gid)) = (self.config.uid(), self.config.gid()) {
+            debug!("changing owner of {} to {}:{}", target.display(), uid, gid);
+            chown(target, Some(Uid::from_raw(uid)), Some(Gid::from_raw(gid)))
+                .context(format!("unable to change owner of {}", target.display()))?;
+        }
+
+        let ns_path = self.namespace_source(namespace);
         debug!("mounting {}", ns_path.display());
-        mount::<_, _, PathBuf, PathBuf>(Some(&ns_path), &bind_path, None, MsFlags::MS_BIND, None)
-            .context(format!(
+        self.bind_mount(&ns_path, target).context(format!(
             "unable to bind mount namespace {}",
             ns_path.display()
-        ))?;
+        ))
+    }
+
+    /// Bind a single namespace and record the created target
+    fn bind_namespace(&self, namespace: Namespace, targets: &mut Vec<BindTarget>) -> Result<()> {
+        let bind_path = self.config.bind_path_for_namespace(namespace.name());
+        debug!("binding namespace: {}", bind_path.display());
+
+        if bind_path.exists() && self.config.force() {
+            debug!("replacing existing namespace file {}", bind_path.display());
+            remove_pin(&bind_path, MntFlags::MNT_DETACH)?;
+        } else if bind_path.exists()
+            && self.config.overwrite_existing_empty()
+            && is_empty_file(&bind_path)
+            && !mountinfo::is_mountpoint(&bind_path)?
+        {
+            debug!("replacing empty namespace file {}", bind_path.display());
+            remove_file(&bind_path).context(format!("unable to remove {}", bind_path.display()))?;
+        } else if bind_path.exists() {
+            if mountinfo::is_mountpoint(&bind_path)? {
+                bail!(
+                    "namespace is already pinned to {}, run `pinns unpin` to remove it",
+                    bind_path.display()
+                )
+            }
+            bail!("namespace file {} already exists", bind_path.display())
+        }
+
+        // The file only has to exist as mount target, which the parent
+        // directory descriptor is only required for
+        let mode = Mode::from_bits_truncate(self.config.file_mode());
+        let dir = self.config.dir_for_namespace(namespace.name());
+        let dir_fd = open_parent_dir(dir, &bind_path, self.config.no_symlinks())?;
+        let created = create_mount_target(dir_fd, &bind_path, mode);
+        let (fd, anonymous) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                close(dir_fd).context("unable to close file descriptor")?;
+                return Err(e);
+            }
+        };
+
+        // The mode of open is subject to the umask
+        let mut res = fchmod(fd, mode).context(format!(
+            "unable to set permissions of {}",
+            bind_path.display()
+        ));
+        if anonymous && res.is_ok() {
+            res = link_mount_target(self.config.proc_path(), fd, dir_fd, &bind_path);
+        }
+        if !anonymous || res.is_ok() {
+            targets.push(BindTarget {
+                namespace: *namespace.name(),
+                path: bind_path.clone(),
+                mounted: false,
+            });
+        }
+        close(dir_fd).context("unable to close file descriptor")?;
+
+        // Without symlinks the file is referred to via its descriptor, which
+        // cannot be redirected by swapping the path until the mount is done
+        let target = if self.config.no_symlinks() {
+            self.config.proc_path().join("self/fd").join(fd.to_string())
+        } else {
+            bind_path.clone()
+        };
+        let res = res.and_then(|_| self.mount_target(&namespace, &target));
+        close(fd).context("unable to close file descriptor")?;
+        res?;
+
+        if let Some(target) = targets.last_mut() {
+            target.mounted = true;
+        }
+
+        if let Some(flag) = self.config.propagation().flag() {
+            debug!("changing mount propagation of {}", bind_path.display());
+            mount::<PathBuf, _, PathBuf, PathBuf>(None, &bind_path, None, flag, None).context(
+                format!("unable to change mount propagation of {}", bind_path.display()),
+            )?;
+        }
+
+        // All flags have to be applied at once, because remounting replaces
+        // the per-mount flags
+        let remount_flags = self.config.remount_flags();
+        if !remount_flags.is_empty() {
+            debug!(
+                "remounting {} with {:?}",
+                bind_path.display(),
+                remount_flags
+            );
+            mount::<PathBuf, _, PathBuf, PathBuf>(
+                None,
+                &bind_path,
+                None,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | remount_flags,
+                None,
+            )
+            .context(format!("unable to remount {}", bind_path.display()))?;
+            if self.config.read_only() && !mountinfo::is_read_only_mountpoint(&bind_path)? {
+                bail!("bind mount {} is not read-only", bind_path.display())
+            }
+        }
+
+        if self.config.verify() && !mountinfo::is_nsfs_mountpoint(&bind_path)? {
+            bail!("bind mount {} is not backed by nsfs", bind_path.display())
+        }
+        info!("pinned {} namespace to {}", namespace.name(), bind_path.display());
+        Ok(())
+    }
+}
+
+/// Log which namespaces are supported by the running kernel
+fn log_kernel_support(proc_path: &Path) {
+    let (supported, unsupported): (Vec<&str>, Vec<&str>) =
+        NAMESPACE_NAMES.iter().copied().partition(|x| namespace_supported(proc_path, x));
+    debug!("kernel supported namespaces: {}", supported.join(", "));
+    if !unsupported.is_empty() {
+        debug!("kernel unsupported namespaces: {}", unsupported.join(", "));
+    }
+}
+
+/// Find the namespaces pinned to `dir`/`name``suffix`/`filename`
+fn pinned_namespaces(dir: &Path, suffix: &str) -> Result<Vec<ListEntry>> {
+    let mountpoints = mountinfo::mountpoints()?;
+    let mut entries = vec![];
+
+    for ns_dir in read_dir(dir).context(format!("unable to read {}", dir.display()))? {
+        let ns_dir = ns_dir?.path();
+        let namespace = match ns_dir
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_suffix(suffix))
+        {
+            Some(name) if ns_dir.is_dir() && Namespace::from_name(name).is_some() => {
+                name.to_string()
+            }
+            _ => continue,
+        };
+
+        for file in read_dir(&ns_dir).context(format!("unable to read {}", ns_dir.display()))? {
+            let path = file?.path();
+            entries.push(ListEntry {
+                namespace: namespace.clone(),
+                active: canonicalize(&path).map_or(false, |x| mountpoints.contains(&x)),
+                path,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Remove the stale namespace files and the then empty namespace directories
+/// below `dir`
+///
+/// Only empty regular files get removed, every active bind mount or file with
+/// content stays untouched.
+fn gc_pins(dir: &Path, suffix: &str, keep_dirs: bool) -> Result<()> {
+    for entry in pinned_namespaces(dir, suffix)?.iter().filter(|x| !x.active) {
+        let path = &entry.path;
+        if !is_empty_file(path) {
+            debug!("keeping {}, which is not an empty file", path.display());
+            continue;
+        }
+        remove_file(path).context(format!("unable to remove {}", path.display()))?;
+        info!("removed stale {}", path.display());
+    }
+
+    if keep_dirs {
+        return Ok(());
+    }
+    for name in NAMESPACE_NAMES {
+        remove_dir_if_empty(&parent_dir_for_namespace(dir, name, suffix))?;
+    }
+    Ok(())
+}
+
+/// Remove the directory if it exists and is empty
+fn remove_dir_if_empty(dir: &Path) -> Result<()> {
+    if dir.is_dir() && read_dir(dir)?.next().is_none() {
+        remove_dir(dir).context(format!("unable to remove {}", dir.display()))?;
+        info!("removed empty {}", dir.display());
+    }
+    Ok(())
+}
+
+/// Retrieve the inode numbers of the pinned namespaces, which the bind mounts
+/// share with their namespaces
+fn namespace_inodes(paths: &PinnedPaths) -> Result<PinnedInodes> {
+    paths
+        .iter()
+        .map(|(name, path)| {
+            let inode = stat(path).context(format!("unable to stat {}", path.display()))?.st_ino;
+            Ok((name.clone(), inode))
+        })
+        .collect()
+}
+
+/// Join the namespaces of the origin via their file descriptors
+///
+/// The user namespace gets skipped in a first pass, which skips failures as
+/// well. The second pass joins the user namespace first and retries the
+/// failed ones, which may be owned by it. This way the user namespace gets
+/// entered last by privileged and first by unprivileged callers.
+fn setns_all(origin: &str, fds: &[(Namespace, RawFd)]) -> Result<()> {
+    let mut joined = vec![false; fds.len()];
+    for first_pass in &[true, false] {
+        for (i, (ns, fd)) in fds.iter().enumerate() {
+            if joined[i] || (*first_pass && ns.name() == "user") {
+                continue;
+            }
+            debug!("joining {} namespace of {}", ns.name(), origin);
+            match setns(*fd, ns.clone_flag()) {
+                Ok(_) => joined[i] = true,
+                Err(e) if *first_pass => debug!(
+                    "retrying to join {} namespace after the user namespace: {}",
+                    ns.name(),
+                    e
+                ),
+                Err(e) => {
+                    return Err(e)
+                        .context(format!("unable to join {} namespace of {}", ns.name(), origin))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the path is an empty regular file, without following symlinks
+fn is_empty_file(path: &Path) -> bool {
+    path.symlink_metadata().map_or(false, |x| x.is_file() && x.len() == 0)
+}
+
+/// Unmount and remove the pinned namespace file, ignoring an already unmounted
+/// or removed one
+fn remove_pin(path: &Path, flags: MntFlags) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    // Unprivileged processes cannot unmount anything, so plain files are
+    // removed without trying to
+    if mountinfo::is_mountpoint(path)? {
+        match umount2(path, flags) {
+            Err(e) if e.as_errno() == Some(Errno::EINVAL) => {
+                debug!("{} is not mounted", path.display())
+            }
+            Err(e) if e.as_errno() == Some(Errno::ENOENT) => {}
+            res => res.context(format!("unable to unmount {}", path.display()))?,
+        }
+    }
+
+    match remove_file(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res.context(format!("unable to remove {}", path.display())),
+    }
+}
+
+/// Add the `key=value` pairs of the message as fields to the JSON object,
+/// whereas numeric values stay numbers
+fn add_fields(line: &mut serde_json::Value, message: &str) {
+    for field in message.split_whitespace() {
+        let mut parts = field.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            line[key] = value
+                .parse::<u64>()
+                .map_or_else(|_| value.into(), serde_json::Value::from);
+        }
+    }
+}
+
+/// Pin the namespaces of a single request within a forked worker, returning
+/// the exit code of the worker
+///
+/// The request is based on the configuration of the daemon, so that only the
+/// directory, filename and namespaces differ between the requests.
+fn serve_request(config: &Config, serve: &Serve, stream: &UnixStream) -> i32 {
+    let res = server::read_request(stream).and_then(|request| {
+        let dir = match &request.dir {
+            Some(dir) => server::request_dir(serve.dir(), dir).context(Failure::Config)?,
+            None => serve.dir().clone(),
+        };
+        let config = config
+            .for_request(dir, request.filename, &request.namespaces)
+            .context(Failure::Config)?;
+        let paths = Pinns::new(config).pin_namespaces()?;
+        Ok(paths.unwrap_or_default())
+    });
+    if let Err(e) = &res {
+        error!("unable to serve request: {:#}", e);
+    }
+    match server::write_response(stream, &res) {
+        Ok(()) if res.is_ok() => 0,
+        Ok(()) => Failure::exit_code_for(res.as_ref().unwrap_err()),
+        Err(e) => {
+            error!("{:#}", e);
+            1
+        }
+    }
+}
+
+/// Collect the exit status of all terminated workers without blocking,
+/// returning their number
+fn reap_workers() -> usize {
+    let mut reaped = 0;
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(_) => break reaped,
+            Ok(status) => {
+                debug!("worker terminated: {:?}", status);
+                reaped += 1;
+            }
+        }
+    }
+}
+
+/// Open the parent directory of the path below `dir`, in which the namespace
+/// file gets created
+///
+/// Without symlinks `dir` itself may still be a symlink, but the remainder of
+/// the path gets resolved beneath it via openat2(2), refusing every symlink.
+/// Kernels lacking openat2(2) open the remainder one directory at a time.
+fn open_parent_dir(dir: &Path, path: &Path, no_symlinks: bool) -> Result<RawFd> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let flags = OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+    if !no_symlinks {
+        return open(parent, flags, Mode::empty())
+            .context(format!("unable to open directory {}", parent.display()));
+    }
+
+    let relative = parent.strip_prefix(dir).context(format!(
+        "directory {} is not below {}",
+        parent.display(),
+        dir.display()
+    ))?;
+    let dir_fd = open(dir, flags, Mode::empty())
+        .context(format!("unable to open directory {}", dir.display()))?;
+    if relative.as_os_str().is_empty() {
+        return Ok(dir_fd);
+    }
+
+    let resolve = kernel::RESOLVE_BENEATH | kernel::RESOLVE_NO_SYMLINKS;
+    let res = match kernel::openat2(dir_fd, relative, flags, resolve) {
+        Err(e) if e.as_errno() == Some(Errno::ENOSYS) => {
+            debug!("openat2 is not supported, opening {} stepwise", parent.display());
+            open_beneath(dir_fd, relative)
+        }
+        res => res,
+    };
+    let closed = close(dir_fd);
+    let fd = match res {
+        Err(e) if e.as_errno() == Some(Errno::ELOOP) || e.as_errno() == Some(Errno::EXDEV) => {
+            bail!("directory {} must not contain symlinks", parent.display())
+        }
+        res => res.context(format!("unable to open directory {}", parent.display()))?,
+    };
+    if let Err(e) = closed {
+        let _ = close(fd);
+        return Err(e).context("unable to close file descriptor");
+    }
+    Ok(fd)
+}
+
+/// Open the relative directory below the directory descriptor without
+/// following any symlink, for kernels lacking openat2(2)
+fn open_beneath(dir_fd: RawFd, relative: &Path) -> nix::Result<RawFd> {
+    if !relative.components().all(|x| matches!(x, Component::Normal(_))) {
+        return Err(nix::Error::Sys(Errno::EXDEV));
+    }
+    // A symlink itself would be opened as `O_PATH` descriptor
+    let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let mut fd = None;
+    for name in relative.iter() {
+        let res = openat(fd.unwrap_or(dir_fd), name, flags, Mode::empty());
+        if let Some(fd) = fd {
+            let _ = close(fd);
+        }
+        fd = Some(res?);
+    }
+    // The relative path is never empty
+    Ok(fd.unwrap_or(dir_fd))
+}
+
+/// Create the file to bind mount a namespace to below the parent directory
+/// descriptor, which must not exist yet
+///
+/// The file gets created anonymously via `O_TMPFILE` if the file system
+/// supports it, which is indicated by the returned flag. Such a file has to be
+/// linked into place via `link_mount_target`, so that it never shows up at
+/// the path with the wrong permissions. The descriptor is close-on-exec, so
+/// that it cannot leak into a forked child executing a command.
+fn create_mount_target(dir_fd: RawFd, path: &Path, mode: Mode) -> Result<(RawFd, bool)> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let flags = OFlag::O_TMPFILE | OFlag::O_WRONLY | OFlag::O_CLOEXEC;
+    match openat(dir_fd, ".", flags, mode) {
+        Ok(fd) => return Ok((fd, true)),
+        Err(e)
+            if matches!(
+                e.as_errno(),
+                Some(Errno::EOPNOTSUPP) | Some(Errno::EISDIR) | Some(Errno::EINVAL)
+            ) =>
+        {
+            debug!("{} does not support O_TMPFILE", parent.display())
+        }
+        Err(e) => {
+            return Err(e).context(format!("unable to create namespace file {}", path.display()))
+        }
+    }
+
+    let fd = openat(
+        dir_fd,
+        path.file_name().unwrap_or_else(|| path.as_os_str()),
+        OFlag::O_RDONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_CLOEXEC,
+        mode,
+    )
+    .context(format!("unable to create namespace file {}", path.display()))?;
+    Ok((fd, false))
+}
+
+/// Link the anonymous file created by `create_mount_target` to the path below
+/// the parent directory descriptor, which fails if the path exists already
+///
+/// Linking via the proc entry of the descriptor does not require the
+/// `CAP_DAC_READ_SEARCH` capability, in contrast to `AT_EMPTY_PATH`.
+fn link_mount_target(proc_path: &Path, fd: RawFd, dir_fd: RawFd, path: &Path) -> Result<()> {
+    let source = proc_path.join("self/fd").join(fd.to_string());
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let name = path.file_name().unwrap_or_else(|| path.as_os_str());
+    let target = CString::new(name.as_bytes())?;
+    let res = unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            dir_fd,
+            target.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    };
+    Errno::result(res).context(format!("unable to link namespace file {}", path.display()))?;
+    Ok(())
+}
+
+/// Join a new anonymous session keyring, which replaces the keyring
+/// inherited from the host
+///
+/// Kernels built without keyring support only get warned about.
+fn join_session_keyring() -> Result<()> {
+    debug!("joining new session keyring");
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_JOIN_SESSION_KEYRING,
+            std::ptr::null::<libc::c_char>(),
+        )
+    };
+    match Errno::result(res) {
+        Ok(_) => Ok(()),
+        Err(e) if e.as_errno() == Some(Errno::ENOSYS) => {
+            warn!("keyrings are not supported by the running kernel");
+            Ok(())
+        }
+        Err(e) => Err(e).context("unable to join new session keyring"),
+    }
+}
+
+/// Move the process into the cgroup v2 directory by writing its PID to
+/// `cgroup.procs`
+fn move_to_cgroup(path: &Path) -> Result<()> {
+    debug!("moving process into cgroup {}", path.display());
+    let procs = path.join("cgroup.procs");
+    write(&procs, getpid().to_string()).context(format!("unable to write {}", procs.display()))
+}
+
+/// Whether a failed mount is worth to be retried
+fn is_retryable(err: &nix::Error) -> bool {
+    matches!(err.as_errno(), Some(Errno::EBUSY) | Some(Errno::EAGAIN))
+}
+
+/// Write the mappings to the provided ID map file, which has to happen in a single write
+fn write_id_mapping(path: &Path, mappings: &[IdMapping]) -> Result<()> {
+    let content = mappings
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    debug!("writing {}", path.display());
+    write(path, content).context(format!("unable to write {}", path.display()))
+}
+
+/// Create a pipe whose ends get closed on exec and when being dropped
+fn pipe() -> Result<(File, File)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).context("unable to create pipe")?;
+    Ok(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Clap;
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use std::{
+        fs::{create_dir, create_dir_all, metadata, read_link, File},
+        io,
+        os::unix::{
+            fs::{symlink, MetadataExt, PermissionsExt},
+            process::CommandExt,
+        },
+        process::Command,
+    };
+    use tempfile::{tempdir, TempDir};
+
+    /// The arguments of the subcommand the test expects to be parsed
+    macro_rules! subcommand {
+        ($pinns:expr, $variant:ident) => {
+            match $pinns.config.subcommand() {
+                Some(SubCommand::$variant(x)) => x,
+                _ => panic!("no {} subcommand", stringify!($variant)),
+            }
+        };
+    }
+
+    fn pinns_for_dir(dir: &str, args: &[&str]) -> Result<Pinns> {
+        let mut config =
+            Config::parse_from(["pinns", "--dir", dir, "--filename", "pinned"].iter().chain(args));
+        config.validate()?;
+        config.prepare_dirs()?;
+        Ok(Pinns::new(config))
+    }
+
+    #[test]
+    fn unpin_success_not_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let config =
+            Config::parse_from(&["pinns", "unpin", "--dir", pin_dir.as_str(), "-f", "x", "net"]);
+        let pinns = Pinns::new(config);
+        pinns.unpin(subcommand!(pinns, Unpin))
+    }
+
+    #[test]
+    fn unpin_success_remove_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        for keep_dirs in &[false, true] {
+            let ns_dir = dir.path().join("netns");
+            create_dir(&ns_dir)?;
+            File::create(ns_dir.join("x"))?;
+
+            let mut args = vec!["pinns", "unpin", "--dir", pin_dir.as_str(), "-f", "x", "net"];
+            if *keep_dirs {
+                args.push("--keep-dirs");
+            }
+            let pinns = Pinns::new(Config::parse_from(args));
+            pinns.unpin(subcommand!(pinns, Unpin))?;
+            assert!(!ns_dir.join("x").exists());
+            assert_eq!(ns_dir.exists(), *keep_dirs);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unpin_success_index() -> Result<()> {
+        let dir = tempdir()?;
+        let mut paths = PinnedPaths::new();
+        for name in &["net", "uts"] {
+            let ns_dir = dir.path().join(format!("{}ns", name));
+            create_dir(&ns_dir)?;
+            File::create(ns_dir.join("x"))?;
+            paths.insert(name.to_string(), ns_dir.join("x"));
+        }
+        let index_path = dir.path().join(INDEX_FILE);
+        let mut index = Index::default();
+        index.add(&paths);
+        index.write(&index_path)?;
+
+        let pin_dir = dir.path().display().to_string();
+        for (name, remaining) in &[("net", 1), ("uts", 0)] {
+            let args = &["pinns", "unpin", "--dir", pin_dir.as_str(), "-f", "x", name];
+            let pinns = Pinns::new(Config::parse_from(args));
+            pinns.unpin(subcommand!(pinns, Unpin))?;
+            assert_eq!(Index::read(&index_path)?.pins.len(), *remaining);
+        }
+        assert!(!index_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn unpin_success_metadata_file() -> Result<()> {
+        let dir = tempdir()?;
+        let pinned = dir.path().join("pinned");
+        File::create(&pinned)?;
+        let mut paths = PinnedPaths::new();
+        paths.insert("net".into(), pinned.clone());
+        let metadata = dir.path().join("metadata.json");
+        Metadata::new(&paths).write(&metadata)?;
+
+        let pin_dir = dir.path().display().to_string();
+        let metadata_file = metadata.display().to_string();
+        let config = Config::parse_from(&[
+            "pinns",
+            "unpin",
+            "--dir",
+            pin_dir.as_str(),
+            "--metadata-file",
+            metadata_file.as_str(),
+        ]);
+        let pinns = Pinns::new(config);
+        pinns.unpin(subcommand!(pinns, Unpin))?;
+        assert!(!pinned.exists());
+        assert!(!metadata.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_success_mount_and_net_visible_on_host() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--mount", "--net"])?;
+        pinns.unshare()?;
+        let paths = pinns.bind_namespaces()?;
+        assert_eq!(paths.len(), 2);
+
+        // The main thread never left the host mount namespace
+        let mountpoints = mountinfo::mountpoints()?;
+        for path in paths.values() {
+            assert!(mountpoints.contains(&canonicalize(path)?));
+            umount2(path, MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_success_user_and_net() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--user", "--net"])?;
+        pinns.unshare()?;
+        let paths = pinns.bind_namespaces()?;
+        assert_eq!(paths.len(), 2);
+
+        let mountpoints = mountinfo::mountpoints()?;
+        for path in paths.values() {
+            assert!(mountpoints.contains(&canonicalize(path)?));
+        }
+        let inode = metadata(&paths["user"])?.ino();
+        assert_ne!(inode, metadata("/proc/self/ns/user")?.ino());
+        for path in paths.values() {
+            umount2(path, MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_success_read_only() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let mut pinns = pinns_for_dir(&pin_dir, &["--uts", "--read-only"])?;
+        pinns.unshare()?;
+        let paths = pinns.bind_namespaces()?;
+        for path in paths.values() {
+            assert!(mountinfo::is_read_only_mountpoint(path)?);
+            umount2(path, MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn selftest_cleanup() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let pinns = Pinns::new(Config::parse_from(&["pinns", "selftest", "-d", pin_dir.as_str()]));
+        let res = pinns.selftest(subcommand!(pinns, Selftest));
+
+        // Unprivileged runs fail to unshare, but have to clean up as well
+        if Uid::effective().is_root() {
+            res?;
+        }
+        assert_eq!(read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_success_pid_for_children() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--pid"])?;
+        pinns.unshare()?;
+        let paths = pinns.bind_namespaces()?;
+
+        // The unsharing thread itself stays in the PID namespace of its parent
+        let pinned = stat(&paths["pid"])?;
+        let own = stat("/proc/thread-self/ns/pid")?;
+        let children = stat("/proc/thread-self/ns/pid_for_children")?;
+        assert_ne!(pinned.st_ino, own.st_ino);
+        assert_eq!(pinned.st_ino, children.st_ino);
+        umount2(&paths["pid"], MntFlags::MNT_DETACH)?;
+        Ok(())
+    }
+
+    #[test]
+    fn join_success_helper_process() -> Result<()> {
+        // Joining the namespaces of another process requires root privileges
+        skip_unless_root!();
+        let mut helper = Command::new("sleep");
+        helper.arg("60");
+        unsafe {
+            helper.pre_exec(|| {
+                let flags =
+                    CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWNS;
+                unshare(flags).map_err(|e| {
+                    io::Error::from_raw_os_error(e.as_errno().map_or(libc::EINVAL, |x| x as i32))
+                })
+            });
+        }
+        let mut child = helper.spawn()?;
+        let pid = child.id().to_string();
+        let names = ["mnt", "net", "uts"];
+        let inodes = names
+            .iter()
+            .map(|name| stat(&Path::new("/proc").join(&pid).join("ns").join(name)))
+            .map(|x| x.map(|x| x.st_ino))
+            .collect::<nix::Result<Vec<_>>>();
+
+        let dir = tempdir()?;
+        let args = &["--join-pid", pid.as_str(), "--mount", "--net", "--uts"];
+        let res = pinns_for_dir(&dir.path().display().to_string(), args).and_then(|mut pinns| {
+            pinns.join(child.id() as i32)?;
+            pinns.bind_namespaces()
+        });
+        child.kill()?;
+        child.wait()?;
+
+        let (paths, inodes) = (res?, inodes?);
+        for (name, inode) in names.iter().zip(inodes) {
+            assert_eq!(stat(&paths[*name])?.st_ino, inode);
+            umount2(&paths[*name], MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_success_mount_flags() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let args = &["--uts", "--mount-flags", "nosuid,noexec,nodev"];
+        let mut pinns = pinns_for_dir(&pin_dir, args)?;
+        pinns.unshare()?;
+        let paths = pinns.bind_namespaces()?;
+        for path in paths.values() {
+            let options = mountinfo::mount_options(path)?;
+            for flag in &["nosuid", "noexec", "nodev"] {
+                assert!(options.iter().any(|x| x == flag));
+            }
+            umount2(path, MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_collect_dry_run() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let config = Config::parse_from(&["pinns", "--dry-run", "--uts", "-d", &pin_dir]);
+        let mut pinns = Pinns::new(config);
+        assert!(pinns.run_and_collect()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_collect_success() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let mut pinns = Pinns::new(Config::parse_from(&["pinns", "--uts", "-d", &pin_dir]));
+        let paths = pinns.run_and_collect()?;
+        assert_eq!(paths.keys().collect::<Vec<_>>(), vec!["uts"]);
+        for path in paths.values() {
+            assert!(mountinfo::is_mountpoint(path)?);
+            umount2(path, MntFlags::MNT_DETACH)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_and_collect_failed_pin_paths_file() -> Result<()> {
+        skip_unless_root!();
+        let dir = tempdir()?;
+        let pin_dir = dir.path().join("pins");
+        create_dir(&pin_dir)?;
+
+        // Root may write to any directory, but not below a regular file
+        let unwritable = dir.path().join("file");
+        File::create(&unwritable)?;
+        let pin_paths_file = unwritable.join("paths.json");
+
+        let config = Config::parse_from(&[
+            "pinns",
+            "--uts",
+            "-d",
+            &pin_dir.display().to_string(),
+            "--pin-paths-file",
+            &pin_paths_file.display().to_string(),
+        ]);
+        let mut pinns = Pinns::new(config);
+        let err = pinns.run_and_collect().unwrap_err();
+        assert!(format!("{:#}", err).contains("unable to write pinned paths"));
+        assert_eq!(read_dir(&pin_dir)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn mount_tmpfs_failed_non_empty_dir() -> Result<()> {
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--net"])?;
+        let err = pinns.mount_tmpfs().unwrap_err();
+        assert!(err.to_string().starts_with("refusing to mount tmpfs"));
+        assert!(!pinns.tmpfs_mounted);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_failed_existing_file() -> Result<()> {
+        let dir = tempdir()?;
+        let pinns = pinns_for_dir(&dir.path().display().to_string(), &["--net"])?;
+        let bind_path = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        File::create(&bind_path)?;
+
+        let ns = *pinns.config.namespaces().net();
+        let err = pinns.bind_namespace(ns, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_force_existing_file() -> Result<()> {
+        let dir = tempdir()?;
+        let pinns = pinns_for_dir(&dir.path().display().to_string(), &["--net", "--force"])?;
+        let bind_path = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        File::create(&bind_path)?;
+
+        // Forcing replaces the file, so unprivileged runs may only fail to mount
+        let ns = *pinns.config.namespaces().net();
+        let mut targets = vec![];
+        if let Err(e) = pinns.bind_namespace(ns, &mut targets) {
+            assert!(!format!("{:#}", e).contains("already exists"));
+        }
+        assert_eq!(targets.len(), 1);
+        pinns.cleanup(&targets);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_overwrite_existing_empty_file() -> Result<()> {
+        let dir = tempdir()?;
+        let args = &["--net", "--overwrite-existing-empty"];
+        let pinns = pinns_for_dir(&dir.path().display().to_string(), args)?;
+        let bind_path = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        File::create(&bind_path)?;
+
+        // The empty file is no pin, so unprivileged runs may only fail to mount
+        let ns = *pinns.config.namespaces().net();
+        let mut targets = vec![];
+        if let Err(e) = pinns.bind_namespace(ns, &mut targets) {
+            assert!(!format!("{:#}", e).contains("already exists"));
+        }
+        assert_eq!(targets.len(), 1);
+        pinns.cleanup(&targets);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_overwrite_existing_empty_failed_not_empty() -> Result<()> {
+        let dir = tempdir()?;
+        let args = &["--net", "--overwrite-existing-empty"];
+        let pinns = pinns_for_dir(&dir.path().display().to_string(), args)?;
+        let bind_path = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        write(&bind_path, "content")?;
+
+        let ns = *pinns.config.namespaces().net();
+        let err = pinns.bind_namespace(ns, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert_eq!(read_to_string(&bind_path)?, "content");
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_uses_bind_path_for_namespace() -> Result<()> {
+        let dir = tempdir()?;
+        let pinns = pinns_for_dir(&dir.path().display().to_string(), &["--net"])?;
+
+        let ns = *pinns.config.namespaces().net();
+        let mut targets = vec![];
+        let _ = pinns.bind_namespace(ns, &mut targets);
+        assert_eq!(targets[0].path, pinns.config.bind_path_for_namespace("net"));
+        pinns.cleanup(&targets);
+        Ok(())
+    }
+
+    #[test]
+    fn remove_pin_regular_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("pinned");
+        File::create(&path)?;
+        remove_pin(&path, MntFlags::MNT_DETACH)?;
+        assert!(!path.exists());
+        remove_pin(&path, MntFlags::empty())
+    }
+
+    #[test]
+    fn pinned_namespaces_stale() -> Result<()> {
+        let dir = tempdir()?;
+        for path in &["netns", "utsns", "foons", "other"] {
+            create_dir(dir.path().join(path))?;
+        }
+        File::create(dir.path().join("netns").join("pinned"))?;
+        File::create(dir.path().join("foons").join("pinned"))?;
+        File::create(dir.path().join("other").join("pinned"))?;
+
+        assert_eq!(
+            pinned_namespaces(dir.path(), "ns")?,
+            vec![ListEntry {
+                namespace: "net".into(),
+                path: dir.path().join("netns").join("pinned"),
+                active: false,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pinned_namespaces_empty_suffix() -> Result<()> {
+        let dir = tempdir()?;
+        for path in &["net", "utsns"] {
+            create_dir(dir.path().join(path))?;
+            File::create(dir.path().join(path).join("pinned"))?;
+        }
+
+        let entries = pinned_namespaces(dir.path(), "")?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, dir.path().join("net").join("pinned"));
+        Ok(())
+    }
+
+    #[test]
+    fn gc_pins_stale() -> Result<()> {
+        let dir = tempdir()?;
+        for path in &["netns", "utsns", "ipcns"] {
+            create_dir(dir.path().join(path))?;
+        }
+        File::create(dir.path().join("netns").join("pinned"))?;
+        let kept = dir.path().join("utsns").join("pinned");
+        write(&kept, "content")?;
+
+        // Running again has nothing left to do
+        gc_pins(dir.path(), "ns", false)?;
+        gc_pins(dir.path(), "ns", false)?;
+        assert!(!dir.path().join("netns").exists());
+        assert!(!dir.path().join("ipcns").exists());
+        assert!(kept.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn gc_pins_keep_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let ns_dir = dir.path().join("netns");
+        create_dir(&ns_dir)?;
+        File::create(ns_dir.join("pinned"))?;
+
+        gc_pins(dir.path(), "ns", true)?;
+        assert!(!ns_dir.join("pinned").exists());
+        assert!(ns_dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn unshare_user_namespace_in_holder() -> Result<()> {
+        // The holder is single-threaded, unlike the test harness
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--user", "--net"])?;
+        match pinns.unshare() {
+            Ok(()) => {
+                let names =
+                    pinns.namespace_fds.iter().map(|(ns, _)| *ns.name()).collect::<Vec<_>>();
+                assert_eq!(names, ["user", "net"]);
+
+                // The test process itself keeps its user namespace
+                let source = pinns.namespace_source(pinns.config.namespaces().user());
+                assert_ne!(read_link(source)?, read_link("/proc/self/ns/user")?);
+            }
+            // Kernels may lack or restrict user namespaces
+            Err(e) => assert!(e.to_string().starts_with("failed to unshare user namespace")),
+        }
+        Ok(())
+    }
+
+    /// A proc path with the namespaces of the test process, whose own entries
+    /// and the ones of process `1` are regular files
+    fn fake_proc_path() -> Result<TempDir> {
+        let dir = tempdir()?;
+        create_dir(dir.path().join("self"))?;
+        symlink("/proc/self/ns", dir.path().join("self/ns"))?;
+        create_dir(dir.path().join("1"))?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn write_id_mappings_success_proc_path() -> Result<()> {
+        let proc_dir = fake_proc_path()?;
+        let dir = tempdir()?;
+        let pinns = pinns_for_dir(
+            &dir.path().display().to_string(),
+            &[
+                "--proc-path",
+                &proc_dir.path().display().to_string(),
+                "--user",
+                "--uid-map",
+                "0:1000:1",
+                "--gid-map",
+                "0:1000:1",
+            ],
+        )?;
+        pinns.write_id_mappings("1")?;
+
+        let process = proc_dir.path().join("1");
+        assert_eq!(read_to_string(process.join("uid_map"))?, "0 1000 1");
+        assert_eq!(read_to_string(process.join("gid_map"))?, "0 1000 1");
+        assert_eq!(process.join("setgroups").exists(), !Uid::effective().is_root());
+        Ok(())
+    }
+
+    #[test]
+    fn write_time_offsets_success_proc_path() -> Result<()> {
+        if !namespace_supported(Path::new("/proc"), "time") {
+            return Ok(());
+        }
+        let proc_dir = fake_proc_path()?;
+        let dir = tempdir()?;
+        let pinns = pinns_for_dir(
+            &dir.path().display().to_string(),
+            &[
+                "--proc-path",
+                &proc_dir.path().display().to_string(),
+                "--time",
+                "--time-offset-monotonic",
+                "5",
+            ],
+        )?;
+        pinns.write_time_offsets()?;
+        assert_eq!(
+            read_to_string(proc_dir.path().join("self/timens_offsets"))?,
+            "monotonic 5 0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unshare_skips_namespace_with_source() -> Result<()> {
+        // Nothing has to be unshared, which works unprivileged as well
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let mut pinns = pinns_for_dir(&pin_dir, &["--net-source", "/proc/self/ns/net"])?;
+        pinns.unshare()?;
+        let source = pinns.config.namespace_source(pinns.config.namespaces().net());
+        assert_eq!(source, PathBuf::from("/proc/self/ns/net"));
+        Ok(())
+    }
+
+    #[test]
+    fn add_fields_timing() {
+        let mut line = serde_json::json!({ "message": "step=bind namespace=net duration_us=42" });
+        add_fields(&mut line, "step=bind namespace=net duration_us=42");
+        assert_eq!(line["step"], "bind");
+        assert_eq!(line["namespace"], "net");
+        assert_eq!(line["duration_us"], 42);
+    }
+
+    #[test]
+    fn create_mount_target_close_on_exec() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("pinned");
+        let dir_fd = open_parent_dir(dir.path(), &path, false)?;
+        let (fd, anonymous) = create_mount_target(dir_fd, &path, Mode::from_bits_truncate(0o644))?;
+        let flags = fcntl(fd, FcntlArg::F_GETFD);
+        let linked = if anonymous {
+            link_mount_target(Path::new("/proc"), fd, dir_fd, &path)
+        } else {
+            Ok(())
+        };
+        close(fd)?;
+        close(dir_fd)?;
+        linked?;
+        assert!(FdFlag::from_bits_truncate(flags?).contains(FdFlag::FD_CLOEXEC));
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn link_mount_target_failed_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("pinned");
+        File::create(&path)?;
+        let dir_fd = open_parent_dir(dir.path(), &path, false)?;
+        // Without O_TMPFILE support creating the file fails already
+        let mode = Mode::from_bits_truncate(0o644);
+        if let Ok((fd, anonymous)) = create_mount_target(dir_fd, &path, mode) {
+            assert!(anonymous);
+            let linked = link_mount_target(Path::new("/proc"), fd, dir_fd, &path);
+            close(fd)?;
+            assert!(linked.is_err());
+        }
+        close(dir_fd)?;
+        Ok(())
+    }
+
+    #[test]
+    fn open_parent_dir_failed_symlink() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir(dir.path().join("real"))?;
+        symlink(dir.path().join("real"), dir.path().join("netns"))?;
+        let path = dir.path().join("netns").join("pinned");
+
+        close(open_parent_dir(dir.path(), &path, false)?)?;
+        let err = open_parent_dir(dir.path(), &path, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("directory {} must not contain symlinks", dir.path().join("netns").display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn open_parent_dir_success_symlinked_dir() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("real").join("netns"))?;
+        let link = dir.path().join("link");
+        symlink(dir.path().join("real"), &link)?;
+
+        // Only the part below the directory must not contain symlinks
+        let path = link.join("netns").join("pinned");
+        close(open_parent_dir(&link, &path, true)?)?;
+        close(open_parent_dir(&link, &link.join("pinned"), true)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn is_retryable_errno() {
+        assert!(is_retryable(&nix::Error::Sys(Errno::EBUSY)));
+        assert!(is_retryable(&nix::Error::Sys(Errno::EAGAIN)));
+        assert!(!is_retryable(&nix::Error::Sys(Errno::EPERM)));
+        assert!(!is_retryable(&nix::Error::InvalidPath));
+    }
+
+    #[test]
+    fn bind_namespaces_cleanup_on_failure() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().join("pins").display().to_string();
+        let mut pinns = pinns_for_dir(&pin_dir, &["--ipc", "--net"])?;
+
+        // Occupy the bind path of the network namespace to let it fail
+        let occupied = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        File::create(&occupied)?;
+
+        assert!(pinns.bind_namespaces().is_err());
+
+        assert!(!pinns.config.parent_dir_for_namespace("ipc").exists());
+        assert!(occupied.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespaces_cleanup_on_concurrent_failure() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().join("pins").display().to_string();
+        let mut pinns = pinns_for_dir(&pin_dir, &["--ipc", "--net", "--uts", "--jobs", "0"])?;
+
+        // Occupy the bind path of the network namespace to let it fail
+        let occupied = pinns.config.parent_dir_for_namespace("net").join("pinned");
+        File::create(&occupied)?;
+
+        let err = pinns.bind_namespaces().unwrap_err();
+        assert!(err.to_string().starts_with("unable to bind "));
+        assert!(!pinns.config.parent_dir_for_namespace("ipc").exists());
+        assert!(!pinns.config.parent_dir_for_namespace("uts").exists());
+        assert!(occupied.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn check_shared_mounts_strict() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        let mut pinns = pinns_for_dir(&pin_dir, &["--net"])?;
+        pinns.config.prepare_dirs()?;
+        pinns.check_shared_mounts()?;
+
+        let pinns = pinns_for_dir(&pin_dir, &["--net", "--strict"])?;
+        let shared = mountinfo::is_on_shared_mount(dir.path())?;
+        assert_eq!(pinns.check_shared_mounts().is_err(), shared);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_jobs_limit() -> Result<()> {
+        let dir = tempdir()?;
+        let pin_dir = dir.path().display().to_string();
+        assert_eq!(pinns_for_dir(&pin_dir, &["--net"])?.bind_jobs(3), 3);
+        assert_eq!(pinns_for_dir(&pin_dir, &["--net", "-j", "2"])?.bind_jobs(3), 2);
+        assert_eq!(pinns_for_dir(&pin_dir, &["--net", "-j", "8"])?.bind_jobs(3), 3);
+        assert_eq!(pinns_for_dir(&pin_dir, &["--net", "-j", "1"])?.bind_jobs(3), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_file_mode() -> Result<()> {
+        let dir = tempdir()?;
+        let args = ["--mount", "--file-mode", "0600"];
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &args)?;
+
+        // Refer to a non existing file descriptor to let the mount fail
+        pinns.mnt_fd = Some(RawFd::max_value());
+        let ns = *pinns.config.namespaces().mnt();
+        assert!(pinns.bind_namespace(ns, &mut vec![]).is_err());
+
+        let bind_path = pinns.config.parent_dir_for_namespace("mnt").join("pinned");
+        assert_eq!(metadata(bind_path)?.permissions().mode() & 0o7777, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn bind_namespace_no_fd_leak_on_mount_failure() -> Result<()> {
+        let dir = tempdir()?;
+        let mut pinns = pinns_for_dir(&dir.path().display().to_string(), &["--mount"])?;
+
+        // Refer to a non existing file descriptor to let the mount fail
+        pinns.mnt_fd = Some(RawFd::max_value());
+        let ns = *pinns.config.namespaces().mnt();
+
+        // Other test threads open descriptors concurrently, which the
+        // single threaded child does not inherit
+        match fork()? {
+            ForkResult::Child => {
+                let count = || read_dir("/proc/self/fd").map_or(0, Iterator::count);
+                let before = count();
+                let failed = pinns.bind_namespace(ns, &mut vec![]).is_err();
+                let code = if failed && count() == before { 0 } else { 1 };
+                unsafe { libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None)?, WaitStatus::Exited(child, 0));
+            }
+        }
 
+        let bind_path = pinns.config.parent_dir_for_namespace("mnt").join("pinned");
+        assert!(bind_path.exists());
         Ok(())
     }
 }
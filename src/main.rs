@@ -1,16 +1,11 @@
-use log::error;
-use pinns::Pinns;
+use pinns::{Config, Failure, Pinns};
 use std::process::exit;
 
 fn main() {
-    if let Err(e) = Pinns::default().run() {
-        error!(
-            "{}",
-            &e.chain()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join(": ")
-        );
-        exit(1);
+    let mut pinns = Pinns::new(Config::parse_args());
+    if let Err(e) = pinns.run() {
+        pinns.report_error(&e);
+        exit(Failure::exit_code_for(&e));
     }
+    exit(pinns.exit_code())
 }
@@ -0,0 +1,300 @@
+//! Mount information related helpers
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashSet,
+    fs::{canonicalize, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// The mount information of the mount namespace of the current process
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+#[derive(Debug, PartialEq)]
+/// A single mount of the current mount namespace, as listed by mountinfo
+pub struct Mount {
+    /// The unique ID of the mount
+    pub id: u32,
+
+    /// The ID of the parent mount, or of the mount itself for the root of the
+    /// mount tree
+    pub parent_id: u32,
+
+    /// The mount point relative to the root of the process
+    pub mountpoint: PathBuf,
+
+    /// The comma separated per-mount options, like `rw,nosuid`
+    pub options: String,
+
+    /// The optional fields, like `shared:5` or `master:2`, which describe the
+    /// mount propagation
+    pub propagation: Vec<String>,
+
+    /// The file system type, like `nsfs`
+    pub fs_type: String,
+
+    /// The file system specific source, like `/dev/sda1`
+    pub source: String,
+}
+
+impl Mount {
+    /// Parse a single line of mountinfo
+    ///
+    /// The optional fields are of a variable amount and end at the ` - `
+    /// separator, which is followed by the file system type and source.
+    fn parse(line: &str) -> Result<Mount> {
+        let mut parts = line.splitn(2, " - ");
+        let mut fields = parts.next().unwrap_or_default().split_whitespace();
+        let mut fs_fields = parts.next().unwrap_or_default().split_whitespace();
+
+        let mut next_id = || fields.next().and_then(|x| x.parse().ok());
+        let (id, parent_id) = match (next_id(), next_id()) {
+            (Some(id), Some(parent_id)) => (id, parent_id),
+            _ => bail!("invalid mount IDs in mountinfo line {}", line),
+        };
+        let (mountpoint, options) = match (fields.nth(2), fields.next()) {
+            (Some(mountpoint), Some(options)) => (mountpoint, options),
+            _ => bail!("missing mount point in mountinfo line {}", line),
+        };
+        let (fs_type, source) = match (fs_fields.next(), fs_fields.next()) {
+            (Some(fs_type), Some(source)) => (fs_type, source),
+            _ => bail!("missing file system in mountinfo line {}", line),
+        };
+
+        Ok(Mount {
+            id,
+            parent_id,
+            mountpoint: PathBuf::from(unescape(mountpoint)),
+            options: options.into(),
+            propagation: fields.map(String::from).collect(),
+            fs_type: fs_type.into(),
+            source: unescape(source),
+        })
+    }
+
+    /// Check if the mount has the per-mount option, like `ro`
+    pub fn has_option(&self, option: &str) -> bool {
+        self.options.split(',').any(|x| x == option)
+    }
+
+    /// Check if the mount propagates mount events to its peer group
+    pub fn is_shared(&self) -> bool {
+        self.propagation.iter().any(|x| x.starts_with("shared:"))
+    }
+}
+
+/// Check if the provided path is a mount point of the current mount namespace
+pub fn is_mountpoint(path: &Path) -> Result<bool> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(mountpoints()?.contains(&path))
+}
+
+/// Check if the provided path is a mount point backed by the namespace file
+/// system, which is the case for pinned namespaces
+pub fn is_nsfs_mountpoint(path: &Path) -> Result<bool> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(has_nsfs_mountpoint(&mounts()?, &path))
+}
+
+/// Check if the provided path is a mount point with the `ro` mount option
+pub fn is_read_only_mountpoint(path: &Path) -> Result<bool> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(last_mount(&mounts()?, &path).map_or(false, |x| x.has_option("ro")))
+}
+
+/// Retrieve the per-mount options of the provided mount point, like `nosuid`
+pub fn mount_options(path: &Path) -> Result<Vec<String>> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(last_mount(&mounts()?, &path)
+        .map(|x| x.options.split(',').map(String::from).collect())
+        .unwrap_or_default())
+}
+
+/// Check if the provided path resides on a mount with shared propagation, whose
+/// bind mounts propagate to all of its peers
+pub fn is_on_shared_mount(path: &Path) -> Result<bool> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(backing_mount(&mounts()?, &path).map_or(false, Mount::is_shared))
+}
+
+/// Check if the provided path resides on a mount of the unified cgroup v2
+/// hierarchy
+pub fn is_on_cgroup2_mount(path: &Path) -> Result<bool> {
+    let path = canonicalize(path).context(format!("unable to resolve {}", path.display()))?;
+    Ok(backing_mount(&mounts()?, &path).map_or(false, |x| x.fs_type == "cgroup2"))
+}
+
+/// Retrieve all mount points of the current mount namespace
+pub fn mountpoints() -> Result<HashSet<PathBuf>> {
+    Ok(mounts()?.into_iter().map(|x| x.mountpoint).collect())
+}
+
+/// Retrieve all mounts of the current mount namespace
+pub fn mounts() -> Result<Vec<Mount>> {
+    let file = File::open(MOUNTINFO).context(format!("unable to open {}", MOUNTINFO))?;
+    read_mounts(BufReader::new(file)).context(format!("unable to read {}", MOUNTINFO))
+}
+
+/// Parse the mountinfo line by line, without reading it as a whole
+fn read_mounts<R: BufRead>(reader: R) -> Result<Vec<Mount>> {
+    reader
+        .lines()
+        .map(|line| Mount::parse(&line.context("unable to read line")?))
+        .collect()
+}
+
+/// Check if the mounts contain the path as mount point of type nsfs
+fn has_nsfs_mountpoint(mounts: &[Mount], path: &Path) -> bool {
+    mounts
+        .iter()
+        .any(|x| x.fs_type == "nsfs" && x.mountpoint == path)
+}
+
+/// The topmost mount at the mount point of the path
+///
+/// The last matching mount wins, because later mounts stack on top of earlier
+/// ones at the same mount point.
+fn last_mount<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts.iter().filter(|x| x.mountpoint == path).last()
+}
+
+/// The mount backing the path, which is the topmost one with the longest
+/// mount point containing the path
+///
+/// The maximum is the last one of equally long mount points, which makes the
+/// topmost mount win.
+fn backing_mount<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|x| path.starts_with(&x.mountpoint))
+        .max_by_key(|x| x.mountpoint.components().count())
+}
+
+/// Decode the octal escape sequences (like `\040` for a space) of mountinfo
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        res.push_str(&rest[..i]);
+        match rest.get(i + 1..i + 4).and_then(|x| u8::from_str_radix(x, 8).ok()) {
+            Some(c) => {
+                res.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                res.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_success() {
+        assert_eq!(unescape("/tmp/with\\040space"), "/tmp/with space");
+        assert_eq!(unescape("/tmp/tab\\011and\\134"), "/tmp/tab\tand\\");
+        assert_eq!(unescape("/tmp/plain"), "/tmp/plain");
+    }
+
+    #[test]
+    fn mount_parse_success() -> Result<()> {
+        let line = "602 27 0:4 net:[4026532281] /run/pinned\\040ns rw,nosuid shared:5 master:1 \
+                    - nsfs nsfs\\040source rw";
+        assert_eq!(
+            Mount::parse(line)?,
+            Mount {
+                id: 602,
+                parent_id: 27,
+                mountpoint: "/run/pinned ns".into(),
+                options: "rw,nosuid".into(),
+                propagation: vec!["shared:5".into(), "master:1".into()],
+                fs_type: "nsfs".into(),
+                source: "nsfs source".into(),
+            }
+        );
+        assert!(Mount::parse("22 1 0:21 / /proc rw - proc proc rw")?
+            .propagation
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn mount_parse_failed_invalid_line() {
+        assert!(Mount::parse("").is_err());
+        assert!(Mount::parse("x 1 0:21 / /proc rw - proc proc rw").is_err());
+        assert!(Mount::parse("22 1 0:21 / /proc rw shared:12").is_err());
+    }
+
+    #[test]
+    fn read_mounts_success() -> Result<()> {
+        let mountinfo = "1 0 8:1 / / rw shared:1 - ext4 /dev/sda1 rw\n\
+                         22 1 0:21 / /proc rw,nosuid shared:12 - proc proc rw\n";
+        let mounts = read_mounts(mountinfo.as_bytes())?;
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].mountpoint, Path::new("/proc"));
+        assert!(read_mounts("1 0 8:1 / /\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn has_nsfs_mountpoint_success() -> Result<()> {
+        let mountinfo = "22 1 0:21 / /proc rw,nosuid shared:12 - proc proc rw\n\
+                         602 27 0:4 net:[4026532281] /run/pinned\\040ns rw shared:5 - nsfs nsfs rw";
+        let mounts = read_mounts(mountinfo.as_bytes())?;
+        assert!(has_nsfs_mountpoint(&mounts, Path::new("/run/pinned ns")));
+        assert!(!has_nsfs_mountpoint(&mounts, Path::new("/proc")));
+        assert!(!has_nsfs_mountpoint(&mounts, Path::new("/run")));
+        Ok(())
+    }
+
+    #[test]
+    fn last_mount_success() -> Result<()> {
+        let mountinfo = "602 27 0:4 net:[4026532281] /run/net rw,nosuid,nodev - nsfs nsfs rw\n\
+                         603 27 0:4 uts:[4026532282] /run/uts ro,relatime - nsfs nsfs rw\n\
+                         604 602 0:4 net:[4026532281] /run/net rw,noexec - nsfs nsfs rw";
+        let mounts = read_mounts(mountinfo.as_bytes())?;
+        let last = last_mount(&mounts, Path::new("/run/net"));
+        assert_eq!(last.map(|x| x.options.as_str()), Some("rw,noexec"));
+        assert!(!last.map_or(false, |x| x.has_option("ro")));
+        assert!(last_mount(&mounts, Path::new("/run/uts")).map_or(false, |x| x.has_option("ro")));
+        assert!(last_mount(&mounts, Path::new("/run")).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn backing_mount_shared() -> Result<()> {
+        let mountinfo = "1 0 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+                         22 1 0:21 / /run rw,nosuid master:2 - tmpfs tmpfs rw\n\
+                         23 22 0:22 / /run/pinns rw - tmpfs tmpfs rw\n\
+                         24 1 0:23 / /var/lib rw - tmpfs tmpfs rw\n\
+                         25 1 0:23 / /var/lib rw shared:3 - tmpfs tmpfs rw";
+        let mounts = read_mounts(mountinfo.as_bytes())?;
+        let is_shared =
+            |path: &str| backing_mount(&mounts, Path::new(path)).map_or(false, Mount::is_shared);
+        assert!(is_shared("/home/user"));
+        assert!(!is_shared("/run"));
+        assert!(!is_shared("/run/pinns/netns"));
+        assert!(is_shared("/var/lib/pinns"));
+        assert!(backing_mount(&[], Path::new("/")).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn mounts_success() -> Result<()> {
+        assert!(mounts()?.iter().any(|x| x.mountpoint == Path::new("/proc")));
+        Ok(())
+    }
+
+    #[test]
+    fn is_mountpoint_success() -> Result<()> {
+        assert!(is_mountpoint(Path::new("/proc"))?);
+        assert!(!is_mountpoint(Path::new("/proc/self"))?);
+        Ok(())
+    }
+}
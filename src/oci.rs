@@ -0,0 +1,103 @@
+//! Reading the namespaces of an OCI runtime spec
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{fs::File, path::Path};
+
+#[derive(Deserialize)]
+/// The subset of an OCI runtime spec which describes the namespaces
+struct Spec {
+    #[serde(default)]
+    linux: Linux,
+}
+
+#[derive(Default, Deserialize)]
+/// The Linux specific part of the runtime spec
+struct Linux {
+    #[serde(default)]
+    namespaces: Vec<Namespace>,
+}
+
+#[derive(Deserialize)]
+/// A namespace of the container, which gets created unless a path is set
+struct Namespace {
+    #[serde(rename = "type")]
+    /// The OCI type of the namespace, like `network`
+    kind: String,
+
+    #[serde(default)]
+    /// The path of an existing namespace to be joined
+    path: Option<String>,
+}
+
+/// Read the names of the namespaces to be created from the `linux.namespaces`
+/// of the OCI runtime spec
+///
+/// Namespaces with a path refer to existing ones, which are skipped.
+pub fn spec_namespaces(path: &Path) -> Result<Vec<&'static str>> {
+    let file = File::open(path).context(format!("unable to open OCI spec {}", path.display()))?;
+    let spec: Spec = serde_json::from_reader(file)
+        .context(format!("unable to parse OCI spec {}", path.display()))?;
+    namespace_names(&spec)
+}
+
+/// Map the namespaces without a path to the pinns namespace names
+fn namespace_names(spec: &Spec) -> Result<Vec<&'static str>> {
+    let mut names = vec![];
+    for ns in spec.linux.namespaces.iter().filter(|x| x.path.is_none()) {
+        let name = match ns.kind.as_str() {
+            "cgroup" => "cgroup",
+            "ipc" => "ipc",
+            "mount" => "mnt",
+            "network" => "net",
+            "pid" => "pid",
+            "time" => "time",
+            "user" => "user",
+            "uts" => "uts",
+            _ => bail!("unknown OCI namespace type {}", ns.kind),
+        };
+        names.push(name);
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn spec_namespaces_success() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(
+            br#"{
+                "ociVersion": "1.0.2",
+                "linux": {
+                    "namespaces": [
+                        {"type": "pid"},
+                        {"type": "network", "path": "/var/run/netns/pinned"},
+                        {"type": "mount"},
+                        {"type": "uts"}
+                    ]
+                }
+            }"#,
+        )?;
+        assert_eq!(spec_namespaces(file.path())?, ["pid", "mnt", "uts"]);
+        Ok(())
+    }
+
+    #[test]
+    fn spec_namespaces_success_no_linux() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(br#"{"ociVersion": "1.0.2"}"#)?;
+        assert!(spec_namespaces(file.path())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_names_failed_unknown_type() -> Result<()> {
+        let spec: Spec = serde_json::from_str(r#"{"linux": {"namespaces": [{"type": "net"}]}}"#)?;
+        assert!(namespace_names(&spec).is_err());
+        Ok(())
+    }
+}
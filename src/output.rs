@@ -0,0 +1,487 @@
+//! Output related structures
+use anyhow::{bail, format_err, Context, Error, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, remove_file, rename, write, OpenOptions},
+    io::ErrorKind,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The pinned namespace names mapped to their absolute bind paths
+pub type PinnedPaths = BTreeMap<String, PathBuf>;
+
+/// The pinned namespace names mapped to the inode numbers of the namespaces
+pub type PinnedInodes = BTreeMap<String, u64>;
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// The available output formats
+pub enum OutputFormat {
+    /// Human readable output via the logger
+    Text,
+
+    /// JSON document on stdout
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("unknown output format {}", s),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// A pinned namespace found on disk
+pub struct ListEntry {
+    /// The name of the namespace
+    pub namespace: String,
+
+    /// The path where the namespace is pinned to
+    pub path: PathBuf,
+
+    /// Whether the path is still an active bind mount or a stale file
+    pub active: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+/// The sidecar document describing what has been pinned
+pub struct Metadata {
+    /// The version of pinns which pinned the namespaces
+    pub version: String,
+
+    /// The time of pinning in seconds since the Unix epoch
+    pub timestamp: u64,
+
+    /// The names of the pinned namespaces
+    pub namespaces: Vec<String>,
+
+    /// The pinned namespace names mapped to their absolute bind paths
+    pub paths: PinnedPaths,
+}
+
+impl Metadata {
+    /// Create the metadata of the pinned paths at the current time
+    pub fn new(paths: &PinnedPaths) -> Self {
+        Metadata {
+            version: env!("VERSION").into(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+            namespaces: paths.keys().cloned().collect(),
+            paths: paths.clone(),
+        }
+    }
+
+    /// Atomically write the metadata as JSON to the provided file
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string(self).context("unable to serialize metadata")?;
+        write_atomic(path, content.as_bytes())
+            .context(format!("unable to write metadata to {}", path.display()))
+    }
+
+    /// Read the metadata from the provided file
+    pub fn read(path: &Path) -> Result<Self> {
+        let content =
+            read_to_string(path).context(format!("unable to read metadata {}", path.display()))?;
+        serde_json::from_str(&content)
+            .context(format!("unable to parse metadata {}", path.display()))
+    }
+}
+
+/// The file name of the index within the parent directory of the pins
+pub const INDEX_FILE: &str = "pinns.json";
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+/// The index of all namespaces pinned below a directory, which spans multiple
+/// runs of pinns
+pub struct Index {
+    /// The pinned namespaces, sorted by their paths
+    pub pins: Vec<IndexEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// A single pinned namespace of the index
+pub struct IndexEntry {
+    /// The name of the namespace
+    pub namespace: String,
+
+    /// The path where the namespace is pinned to
+    pub path: PathBuf,
+}
+
+impl Index {
+    /// Read the index from the provided file, which is empty if the file does
+    /// not exist yet
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+        let content =
+            read_to_string(path).context(format!("unable to read index {}", path.display()))?;
+        serde_json::from_str(&content).context(format!("unable to parse index {}", path.display()))
+    }
+
+    /// Update the index within the provided file while holding an exclusive
+    /// lock, which serializes concurrent runs of pinns
+    ///
+    /// The lock is taken on a separate file next to the index, because writing
+    /// the index replaces its file.
+    pub fn update<F: FnOnce(&mut Index)>(path: &Path, f: F) -> Result<()> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let lock = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)
+            .context(format!("unable to open index lock {}", lock_path.display()))?;
+        loop {
+            match flock(lock.as_raw_fd(), FlockArg::LockExclusive) {
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => {}
+                res => {
+                    res.context(format!("unable to lock index {}", lock_path.display()))?;
+                    break;
+                }
+            }
+        }
+
+        let mut index = Index::read(path)?;
+        f(&mut index);
+        // The lock gets released by closing its file on return
+        index.write(path)
+    }
+
+    /// Add the pinned paths, replacing previous entries of the same paths
+    pub fn add(&mut self, paths: &PinnedPaths) {
+        for (namespace, path) in paths {
+            self.pins.retain(|x| x.path != *path);
+            self.pins.push(IndexEntry {
+                namespace: namespace.clone(),
+                path: path.clone(),
+            });
+        }
+        self.pins.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// Remove the entries of the unpinned paths
+    pub fn remove(&mut self, paths: &[PathBuf]) {
+        self.pins.retain(|x| !paths.contains(&x.path));
+    }
+
+    /// Atomically write the index as JSON to the provided file, or remove the
+    /// file if nothing is pinned anymore
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if self.pins.is_empty() {
+            return match remove_file(path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                res => res.context(format!("unable to remove index {}", path.display())),
+            };
+        }
+        let content = serde_json::to_string(self).context("unable to serialize index")?;
+        write_atomic(path, content.as_bytes())
+            .context(format!("unable to write index to {}", path.display()))
+    }
+}
+
+/// Print the found pinned namespaces in the provided format
+pub fn print_list(format: OutputFormat, entries: &[ListEntry]) -> Result<()> {
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string(entries).context("unable to serialize pinned namespaces")?
+        ),
+        OutputFormat::Text => {
+            for entry in entries {
+                println!(
+                    "{}\t{}\t{}",
+                    entry.namespace,
+                    entry.path.display(),
+                    if entry.active { "active" } else { "stale" }
+                )
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the pinned paths in the provided format, including the inode
+/// numbers of the namespaces if provided
+///
+/// The text output is left to the logger, unless inode numbers are included.
+pub fn print(
+    format: OutputFormat,
+    paths: &PinnedPaths,
+    inodes: Option<&PinnedInodes>,
+) -> Result<()> {
+    match (format, inodes) {
+        (OutputFormat::Json, _) => println!(
+            "{}",
+            to_json(paths, inodes).context("unable to serialize pinned paths")?
+        ),
+        (OutputFormat::Text, Some(inodes)) => {
+            for (name, path) in paths {
+                println!(
+                    "{}\t{}\t{}",
+                    name,
+                    path.display(),
+                    namespace_id(name, inodes)
+                );
+            }
+        }
+        (OutputFormat::Text, None) => {}
+    }
+    Ok(())
+}
+
+/// Convert the pinned paths into JSON, where every namespace maps to an
+/// object containing its path and inode number if inodes are provided
+fn to_json(paths: &PinnedPaths, inodes: Option<&PinnedInodes>) -> Result<serde_json::Value> {
+    let inodes = match inodes {
+        Some(inodes) => inodes,
+        None => return Ok(serde_json::to_value(paths)?),
+    };
+    let mut map = serde_json::Map::new();
+    for (name, path) in paths {
+        let value = serde_json::json!({
+            "path": serde_json::to_value(path)?,
+            "inode": inodes.get(name),
+            "id": namespace_id(name, inodes),
+        });
+        map.insert(name.clone(), value);
+    }
+    Ok(map.into())
+}
+
+/// The identifier of the namespace like `net:[4026531956]`, as shown by
+/// readlink(1) on its proc entry
+fn namespace_id(name: &str, inodes: &PinnedInodes) -> String {
+    match inodes.get(name) {
+        Some(inode) => format!("{}:[{}]", name, inode),
+        None => format!("{}:[]", name),
+    }
+}
+
+/// Write the pinned paths as JSON to the provided file
+pub fn write_file(path: &Path, paths: &PinnedPaths) -> Result<()> {
+    let content = serde_json::to_string(paths).context("unable to serialize pinned paths")?;
+    write_atomic(path, content.as_bytes())
+        .context(format!("unable to write pinned paths to {}", path.display()))
+}
+
+/// Write the content to a temporary file and rename it to the provided path,
+/// so that readers never observe a partially written file
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format_err!("{} is not a file path", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let res = write(&temp_path, content)
+        .context(format!("unable to write {}", temp_path.display()))
+        .and_then(|_| {
+            rename(&temp_path, path).context(format!("unable to rename {}", temp_path.display()))
+        });
+    if res.is_err() {
+        let _ = remove_file(&temp_path);
+    }
+    res
+}
+
+/// Print the version and build information as JSON to stdout
+pub fn print_version() {
+    println!("{}", version_to_json());
+}
+
+/// The version and build information captured by the build script, where the
+/// build timestamp is in seconds since the epoch
+pub fn version_to_json() -> serde_json::Value {
+    serde_json::json!({
+        "version": env!("VERSION").trim(),
+        "commit": env!("GIT_COMMIT"),
+        "build_timestamp": env!("BUILD_TIMESTAMP").parse::<u64>().unwrap_or_default(),
+        "rustc": env!("RUSTC_VERSION"),
+    })
+}
+
+/// Print the failure including its chain of causes as JSON to stderr
+pub fn print_error(err: &Error) {
+    eprintln!("{}", error_to_json(err));
+}
+
+/// Convert the failure into a JSON object containing the top level message
+/// and all causes
+pub fn error_to_json(err: &Error) -> serde_json::Value {
+    serde_json::json!({
+        "error": err.to_string(),
+        "chain": err.chain().map(|x| x.to_string()).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{
+        fs::{create_dir_all, read_dir},
+        thread,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn output_format_from_str() -> Result<()> {
+        assert_eq!("text".parse::<OutputFormat>()?, OutputFormat::Text);
+        assert_eq!("json".parse::<OutputFormat>()?, OutputFormat::Json);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_file_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("paths.json");
+        let mut paths = PinnedPaths::new();
+        paths.insert("net".into(), "/tmp/netns/uuid".into());
+        write_file(&path, &paths)?;
+        assert_eq!(read_to_string(&path)?, r#"{"net":"/tmp/netns/uuid"}"#);
+        assert_eq!(read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn write_file_failed_cleans_up() -> Result<()> {
+        let dir = tempdir()?;
+        // Renaming a file onto a non empty directory fails
+        let path = dir.path().join("paths.json");
+        create_dir_all(path.join("occupied"))?;
+        assert!(write_file(&path, &PinnedPaths::new()).is_err());
+        assert_eq!(read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_write_read() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("metadata.json");
+        let mut paths = PinnedPaths::new();
+        paths.insert("net".into(), "/tmp/netns/uuid".into());
+        let metadata = Metadata::new(&paths);
+        metadata.write(&path)?;
+        assert_eq!(Metadata::read(&path)?, metadata);
+        assert_eq!(metadata.namespaces, ["net"]);
+        assert!(Metadata::read(&dir.path().join("missing.json")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn version_to_json_success() {
+        let version = version_to_json();
+        assert_eq!(version["version"], env!("VERSION").trim());
+        assert!(version["build_timestamp"].is_u64());
+        assert!(version["rustc"].as_str().unwrap_or_default().starts_with("rustc "));
+    }
+
+    #[test]
+    fn index_add_remove_write() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(INDEX_FILE);
+        let mut index = Index::read(&path)?;
+        assert!(index.pins.is_empty());
+
+        let mut paths = PinnedPaths::new();
+        paths.insert("uts".into(), "/run/utsns/pinned".into());
+        paths.insert("net".into(), "/run/netns/pinned".into());
+        index.add(&paths);
+        index.add(&paths);
+        index.write(&path)?;
+
+        let mut index = Index::read(&path)?;
+        assert_eq!(index.pins.len(), 2);
+        assert_eq!(index.pins[0].namespace, "net");
+
+        index.remove(&["/run/netns/pinned".into()]);
+        assert_eq!(index.pins.len(), 1);
+        index.remove(&["/run/utsns/pinned".into()]);
+        index.write(&path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn index_update_concurrent() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(INDEX_FILE);
+        let writers = (0..16)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let mut paths = PinnedPaths::new();
+                    paths.insert("net".into(), format!("/run/netns/{}", i).into());
+                    Index::update(&path, |x| x.add(&paths))
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().expect("index writer panicked")?;
+        }
+        assert_eq!(Index::read(&path)?.pins.len(), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn error_to_json_chain() {
+        let err = format_err!("inner").context("outer");
+        assert_eq!(
+            error_to_json(&err).to_string(),
+            r#"{"chain":["outer","inner"],"error":"outer"}"#
+        );
+    }
+
+    #[test]
+    fn pinned_paths_to_json() -> Result<()> {
+        let mut paths = PinnedPaths::new();
+        paths.insert("uts".into(), "/tmp/utsns/uuid".into());
+        paths.insert("net".into(), "/tmp/netns/uuid".into());
+        assert_eq!(
+            serde_json::to_string(&paths)?,
+            r#"{"net":"/tmp/netns/uuid","uts":"/tmp/utsns/uuid"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pinned_paths_to_json_with_inodes() -> Result<()> {
+        let mut paths = PinnedPaths::new();
+        paths.insert("net".into(), "/tmp/netns/uuid".into());
+        assert_eq!(
+            to_json(&paths, None)?.to_string(),
+            r#"{"net":"/tmp/netns/uuid"}"#
+        );
+
+        let mut inodes = PinnedInodes::new();
+        inodes.insert("net".into(), 4_026_531_956);
+        assert_eq!(
+            to_json(&paths, Some(&inodes))?.to_string(),
+            r#"{"net":{"id":"net:[4026531956]","inode":4026531956,"path":"/tmp/netns/uuid"}}"#
+        );
+        Ok(())
+    }
+}
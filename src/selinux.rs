@@ -0,0 +1,90 @@
+//! SELinux labeling of the pinned namespace files
+use crate::mountinfo;
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path};
+
+/// The extended attribute holding the SELinux context of a file
+const XATTR_SELINUX: &[u8] = b"security.selinux\0";
+
+/// Check if SELinux is enabled, which is the case if its file system is mounted
+pub fn is_enabled() -> Result<bool> {
+    Ok(mountinfo::mounts()?
+        .iter()
+        .any(|x| x.fs_type == "selinuxfs"))
+}
+
+/// Apply the SELinux context to the path, without following symlinks
+///
+/// The context gets stored including its terminating null byte, like
+/// setfilecon(3) does.
+pub fn set_label(path: &Path, label: &str) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .context(format!("invalid path {}", path.display()))?;
+    let c_label = CString::new(label).context(format!("invalid SELinux label {}", label))?;
+    let value = c_label.as_bytes_with_nul();
+    let res = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            XATTR_SELINUX.as_ptr() as *const libc::c_char,
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    Errno::result(res).context(format!(
+        "unable to set SELinux label {} on {}",
+        label,
+        path.display()
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    fn get_label(path: &Path) -> Result<Vec<u8>> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let mut value = vec![0u8; 256];
+        let res = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                XATTR_SELINUX.as_ptr() as *const libc::c_char,
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        value.truncate(Errno::result(res)? as usize);
+        Ok(value)
+    }
+
+    #[test]
+    fn set_label_success() -> Result<()> {
+        // Relabeling requires a loaded policy besides root privileges
+        skip_unless_root!();
+        if !is_enabled()? {
+            return Ok(());
+        }
+        let dir = tempdir()?;
+        let path = dir.path().join("pinned");
+        File::create(&path)?;
+
+        // The initial label is known to be valid within the loaded policy
+        let initial = String::from_utf8(get_label(&path)?)?;
+        let label = initial.trim_end_matches('\0');
+        set_label(&path, label)?;
+        assert_eq!(get_label(&path)?, format!("{}\0", label).into_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn set_label_failed_not_existing() -> Result<()> {
+        let dir = tempdir()?;
+        let label = "system_u:object_r:container_file_t:s0";
+        assert!(set_label(&dir.path().join("not-existing"), label).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Requests and responses of the pin daemon
+use crate::output::{error_to_json, PinnedPaths};
+use anyhow::{bail, Context, Error, Result};
+use serde::Deserialize;
+use std::{
+    fs::canonicalize,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// A request to pin namespaces, sent as a single line of JSON
+pub struct PinRequest {
+    /// The names of the namespaces to be pinned
+    pub namespaces: Vec<String>,
+
+    #[serde(default)]
+    /// The parent directory for the pinned namespaces, which defaults to the
+    /// one of the daemon and has to be below it
+    pub dir: Option<PathBuf>,
+
+    #[serde(default)]
+    /// The file name the namespaces will be pinned to, which defaults to a
+    /// random UUID
+    pub filename: Option<String>,
+}
+
+/// Read a single request from the connection
+pub fn read_request(stream: &UnixStream) -> Result<PinRequest> {
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("unable to read request")?;
+    serde_json::from_str(&line).context("unable to parse request")
+}
+
+/// Resolve the directory of a request, which has to be below the directory
+/// of the daemon
+///
+/// Relative directories are taken relative to the one of the daemon. The
+/// already existing part of the directory gets resolved as well, so that
+/// symlinks cannot lead outside of it either.
+pub fn request_dir(base: &Path, dir: &Path) -> Result<PathBuf> {
+    let path = base.join(dir);
+    if path.components().any(|x| x == Component::ParentDir) {
+        bail!("request directory {} must not contain `..`", dir.display())
+    }
+    let resolved_base =
+        canonicalize(base).context(format!("unable to resolve {}", base.display()))?;
+    let existing = path.ancestors().find(|x| x.exists()).unwrap_or(base);
+    let resolved =
+        canonicalize(existing).context(format!("unable to resolve {}", existing.display()))?;
+    if !path.starts_with(base) || !resolved.starts_with(&resolved_base) {
+        bail!(
+            "request directory {} is outside of {}",
+            dir.display(),
+            base.display()
+        )
+    }
+    Ok(path)
+}
+
+/// Write the pinned paths or the failure as a single line of JSON to the
+/// connection
+pub fn write_response(mut stream: &UnixStream, res: &Result<PinnedPaths, Error>) -> Result<()> {
+    let response = match res {
+        Ok(paths) => serde_json::json!({ "paths": paths }),
+        Err(e) => error_to_json(e),
+    };
+    writeln!(stream, "{}", response).context("unable to write response")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use anyhow::format_err;
+    use std::{fs::create_dir, io::Read, os::unix::fs::symlink};
+    use tempfile::tempdir;
+
+    #[test]
+    fn read_request_success() -> Result<()> {
+        let (mut client, server) = UnixStream::pair()?;
+        client.write_all(b"{\"namespaces\": [\"net\"], \"filename\": \"pinned\"}\n")?;
+        assert_eq!(
+            read_request(&server)?,
+            PinRequest {
+                namespaces: vec!["net".into()],
+                dir: None,
+                filename: Some("pinned".into()),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_request_failed_unknown_field() -> Result<()> {
+        let (mut client, server) = UnixStream::pair()?;
+        client.write_all(b"{\"namespaces\": [], \"nett\": true}\n")?;
+        assert!(read_request(&server).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn request_dir_success() -> Result<()> {
+        let base = tempdir()?;
+        let nested = base.path().join("nested");
+        create_dir(&nested)?;
+        assert_eq!(request_dir(base.path(), Path::new("nested"))?, nested);
+        assert_eq!(request_dir(base.path(), &nested)?, nested);
+        assert_eq!(
+            request_dir(base.path(), Path::new("new/dir"))?,
+            base.path().join("new/dir")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn request_dir_failed_outside() -> Result<()> {
+        let base = tempdir()?;
+        let outside = tempdir()?;
+        symlink(outside.path(), base.path().join("link"))?;
+        assert!(request_dir(base.path(), outside.path()).is_err());
+        assert!(request_dir(base.path(), Path::new("../other")).is_err());
+        assert!(request_dir(base.path(), Path::new("link/dir")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_response_success_and_failure() -> Result<()> {
+        let (mut client, server) = UnixStream::pair()?;
+        let mut paths = PinnedPaths::new();
+        paths.insert("net".into(), "/tmp/netns/pinned".into());
+        write_response(&server, &Ok(paths))?;
+        write_response(&server, &Err(format_err!("failed")))?;
+        drop(server);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response)?;
+        assert_eq!(
+            response,
+            concat!(
+                "{\"paths\":{\"net\":\"/tmp/netns/pinned\"}}\n",
+                "{\"chain\":[\"failed\"],\"error\":\"failed\"}\n"
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Termination signal handling to clean up interrupted runs
+use anyhow::{Context, Result};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The signals which trigger the cleanup
+const SIGNALS: &[Signal] = &[Signal::SIGINT, Signal::SIGTERM];
+
+/// The last received termination signal, zero if none
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn handle(signal: libc::c_int) {
+    // Storing an atomic is async-signal-safe
+    RECEIVED.store(signal, Ordering::SeqCst);
+}
+
+/// Install the handlers for the termination signals
+///
+/// The handlers only record the signal, which gets checked in between the
+/// steps of pinning. Blocking system calls like waitpid(2) get interrupted,
+/// because `SA_RESTART` is not set.
+pub fn install() -> Result<()> {
+    let action = SigAction::new(SigHandler::Handler(handle), SaFlags::empty(), SigSet::empty());
+    for signal in SIGNALS {
+        unsafe { sigaction(*signal, &action) }
+            .context(format!("unable to install {:?} handler", signal))?;
+    }
+    Ok(())
+}
+
+/// Block until a termination signal gets received
+///
+/// Returns immediately if a signal has been received already. The signals are
+/// blocked while checking for that, so none can get lost in between.
+pub fn wait() -> Result<Signal> {
+    let mut set = SigSet::empty();
+    for signal in SIGNALS {
+        set.add(*signal);
+    }
+    set.thread_block().context("unable to block signals")?;
+    let res = match received() {
+        Some(signal) => Ok(signal),
+        None => set.wait().context("unable to wait for signals"),
+    };
+    set.thread_unblock().context("unable to unblock signals")?;
+    res
+}
+
+/// The termination signal received since installing the handlers, if any
+pub fn received() -> Option<Signal> {
+    Signal::from_c_int(RECEIVED.load(Ordering::SeqCst)).ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn received_none() {
+        assert!(received().is_none())
+    }
+}
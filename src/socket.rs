@@ -0,0 +1,93 @@
+//! Passing namespace file descriptors over unix sockets
+use anyhow::{Context, Result};
+use nix::{
+    sys::{
+        socket::{
+            connect, sendmsg, socket, AddressFamily, ControlMessage, MsgFlags, SockAddr, SockFlag,
+            SockType,
+        },
+        uio::IoVec,
+    },
+    unistd::close,
+};
+use std::{os::unix::io::RawFd, path::Path};
+
+/// Send the namespace file descriptors to the listener of the unix socket
+///
+/// All descriptors are sent via a single `SCM_RIGHTS` control message. The
+/// message data is a JSON array of the namespace names, in the same order as
+/// the descriptors.
+pub fn send_fds(path: &Path, names: &[&str], fds: &[RawFd]) -> Result<()> {
+    let header = serde_json::to_string(names).context("unable to serialize namespace names")?;
+    let addr =
+        SockAddr::new_unix(path).context(format!("invalid socket path {}", path.display()))?;
+    let fd = socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .context("unable to create socket")?;
+
+    let res = connect(fd, &addr)
+        .context(format!("unable to connect to {}", path.display()))
+        .and_then(|_| {
+            let iov = [IoVec::from_slice(header.as_bytes())];
+            let cmsgs = [ControlMessage::ScmRights(fds)];
+            sendmsg(fd, &iov, &cmsgs, MsgFlags::empty(), None).context(format!(
+                "unable to send file descriptors to {}",
+                path.display()
+            ))
+        });
+    close(fd).context("unable to close socket")?;
+    res.map(|_| ())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use nix::{
+        cmsg_space,
+        fcntl::{open, OFlag},
+        sys::{
+            socket::{recvmsg, ControlMessageOwned},
+            stat::Mode,
+        },
+    };
+    use std::os::unix::{io::AsRawFd, net::UnixListener};
+    use tempfile::tempdir;
+
+    #[test]
+    fn send_fds_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("pinns.sock");
+        let listener = UnixListener::bind(&path)?;
+
+        let ns_fd = open("/proc/self/ns/net", OFlag::O_RDONLY, Mode::empty())?;
+        send_fds(&path, &["net"], &[ns_fd])?;
+        close(ns_fd)?;
+
+        let (stream, _) = listener.accept()?;
+        let mut buf = [0u8; 64];
+        let mut cmsg_buffer = cmsg_space!([RawFd; 1]);
+        let msg = recvmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut buf)],
+            Some(&mut cmsg_buffer),
+            MsgFlags::empty(),
+        )?;
+        let fds = msg
+            .cmsgs()
+            .flat_map(|x| match x {
+                ControlMessageOwned::ScmRights(fds) => fds,
+                _ => vec![],
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(&buf[..msg.bytes], br#"["net"]"#);
+        assert_eq!(fds.len(), 1);
+        for fd in fds {
+            close(fd)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Process status related helpers to explain failures to unshare
+use crate::kernel::user_namespaces_disabled;
+use nix::errno::Errno;
+use std::{fs::read_to_string, path::Path};
+
+/// The capability required to unshare all namespaces but the user namespace
+const CAP_SYS_ADMIN: u32 = 21;
+
+/// The seccomp mode of a process running with a filter
+const SECCOMP_MODE_FILTER: u32 = 2;
+
+/// The identity mapping of the initial user namespace
+const INITIAL_ID_MAP: &[&str] = &["0", "0", "4294967295"];
+
+/// Explain the likely cause of a failure to unshare the namespace of the
+/// provided name, if the errno hints at one
+///
+/// The process status and ID mapping get read from the proc file system at
+/// `proc_path`, which is inspected at the time of the failure.
+pub fn unshare_hint(proc_path: &Path, name: &str, errno: Option<Errno>) -> Option<String> {
+    let mut hints = vec![];
+    match errno? {
+        Errno::EPERM => {
+            let status = read_to_string(proc_path.join("self/status")).unwrap_or_default();
+            let uid_map = read_to_string(proc_path.join("self/uid_map")).unwrap_or_default();
+            if name != "user" && !has_capability(&status, CAP_SYS_ADMIN) {
+                hints.push("the process lacks CAP_SYS_ADMIN, which root or --user provides");
+            }
+            if seccomp_mode(&status) == Some(SECCOMP_MODE_FILTER) {
+                hints.push("a seccomp filter is active, which may forbid unshare(2)");
+            }
+            if !uid_map.is_empty() && !is_initial_id_map(&uid_map) {
+                hints.push("the process runs within a user namespace, which may be restricted");
+            }
+        }
+        Errno::ENOSPC | Errno::EUSERS if name == "user" => {
+            if user_namespaces_disabled(proc_path) {
+                hints.push("user namespaces are disabled via max_user_namespaces");
+            } else {
+                hints.push("the limit of nested user namespaces is reached");
+            }
+        }
+        Errno::ENOSPC => hints.push("the limit of namespaces of this type is reached"),
+        Errno::EINVAL if name == "user" => {
+            hints.push("the process is multi-threaded or the kernel lacks user namespaces")
+        }
+        Errno::EINVAL => hints.push("the namespace is not supported by the running kernel"),
+        _ => {}
+    }
+
+    if hints.is_empty() {
+        None
+    } else {
+        Some(hints.join(" or "))
+    }
+}
+
+/// Check if the effective capabilities of the process status contain the
+/// capability
+fn has_capability(status: &str, cap: u32) -> bool {
+    status_field(status, "CapEff")
+        .and_then(|x| u64::from_str_radix(x, 16).ok())
+        .map_or(false, |x| x & (1 << cap) != 0)
+}
+
+/// The seccomp mode of the process status
+fn seccomp_mode(status: &str) -> Option<u32> {
+    status_field(status, "Seccomp").and_then(|x| x.parse().ok())
+}
+
+/// Check if the ID map is the one of the initial user namespace
+fn is_initial_id_map(map: &str) -> bool {
+    let lines = map.lines().collect::<Vec<_>>();
+    lines.len() == 1
+        && lines[0]
+            .split_whitespace()
+            .eq(INITIAL_ID_MAP.iter().copied())
+}
+
+/// The value of a field like `CapEff:\t0000003fffffffff` of the process status
+fn status_field<'a>(status: &'a str, name: &str) -> Option<&'a str> {
+    status.lines().find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key == name => Some(value.trim()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    const STATUS: &str = "Name:\tpinns\nCapEff:\t0000000000200000\nSeccomp:\t2\n";
+
+    #[test]
+    fn has_capability_success() {
+        assert!(has_capability(STATUS, CAP_SYS_ADMIN));
+        assert!(!has_capability(STATUS, 12));
+        assert!(!has_capability("", CAP_SYS_ADMIN));
+    }
+
+    #[test]
+    fn seccomp_mode_success() {
+        assert_eq!(seccomp_mode(STATUS), Some(SECCOMP_MODE_FILTER));
+        assert_eq!(seccomp_mode("Seccomp:\t0\n"), Some(0));
+        assert_eq!(seccomp_mode(""), None);
+    }
+
+    #[test]
+    fn is_initial_id_map_success() {
+        assert!(is_initial_id_map("         0          0 4294967295\n"));
+        assert!(!is_initial_id_map("         0       1000          1\n"));
+    }
+
+    #[test]
+    fn unshare_hint_success() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("self"))?;
+        write(
+            dir.path().join("self/status"),
+            "CapEff:\t0000000000000000\nSeccomp:\t2\n",
+        )?;
+        write(dir.path().join("self/uid_map"), "0 1000 1\n")?;
+
+        let hint = unshare_hint(dir.path(), "net", Some(Errno::EPERM)).unwrap_or_default();
+        assert!(hint.contains("CAP_SYS_ADMIN"));
+        assert!(hint.contains("seccomp"));
+        assert!(hint.contains("user namespace"));
+        assert!(unshare_hint(dir.path(), "net", Some(Errno::EBADF)).is_none());
+        assert!(unshare_hint(dir.path(), "net", None).is_none());
+        Ok(())
+    }
+}
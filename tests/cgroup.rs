@@ -0,0 +1,74 @@
+#[macro_use]
+mod common;
+
+use anyhow::Result;
+use std::{
+    fs::{canonicalize, read_to_string},
+    path::Path,
+    process::{Command, Output},
+};
+use tempfile::tempdir;
+
+fn pinns(dir: &Path, args: &[&str]) -> Result<Output> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir)
+        .args(args)
+        .output()?)
+}
+
+fn cgroup_v2_host() -> bool {
+    Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+#[test]
+fn pin_cgroup() -> Result<()> {
+    // Mounting the pinned cgroup namespace requires root privileges
+    skip_unless_root!();
+
+    // The unified hierarchy gets verified if available, otherwise cgroup v1
+    // is in use
+    let mut args = vec!["--filename", "pinned", "--cgroup"];
+    if cgroup_v2_host() {
+        args.push("--require-cgroup-v2");
+    }
+
+    let dir = tempdir()?;
+    let output = pinns(dir.path(), &args)?;
+    assert!(output.status.success());
+
+    // Without --cgroup-path the cgroup of the test becomes the root
+    let stderr = String::from_utf8(output.stderr)?;
+    let version = if cgroup_v2_host() { "v2" } else { "v1" };
+    for line in read_to_string("/proc/self/cgroup")?.lines() {
+        assert!(stderr.contains(&format!("new cgroup {} namespace root: {}", version, line)));
+    }
+
+    let pinned = canonicalize(dir.path().join("cgroupns").join("pinned"))?;
+    let mountinfo = read_to_string("/proc/self/mountinfo")?;
+    assert!(mountinfo
+        .lines()
+        .filter_map(|x| x.split_whitespace().nth(4))
+        .any(|x| Path::new(x) == pinned));
+
+    assert!(Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["unpin", "--dir"])
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "cgroup"])
+        .status()?
+        .success());
+    assert!(!pinned.exists());
+    Ok(())
+}
+
+#[test]
+fn require_cgroup_v2_failed_v1() -> Result<()> {
+    if cgroup_v2_host() {
+        return Ok(());
+    }
+    let dir = tempdir()?;
+    let output = pinns(dir.path(), &["--cgroup", "--require-cgroup-v2"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)?.contains("host does not use cgroup v2"));
+    Ok(())
+}
@@ -0,0 +1,10 @@
+//! Helpers shared by the integration tests
+
+/// Return early from a test which has to run as root
+macro_rules! skip_unless_root {
+    () => {
+        if !nix::unistd::Uid::effective().is_root() {
+            return Ok(());
+        }
+    };
+}
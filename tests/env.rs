@@ -0,0 +1,103 @@
+use anyhow::Result;
+use pinns::Config;
+use std::{
+    env::{current_exe, temp_dir, var_os},
+    path::Path,
+    process::Command,
+};
+use tempfile::tempdir;
+
+fn pinns_dry_run(dir: &Path, envs: &[(&str, &str)], args: &[&str]) -> Result<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .env("PINNS_DIR", dir)
+        .env("PINNS_DRY_RUN", "1")
+        .envs(envs.iter().copied())
+        .args(args)
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[test]
+fn env_populates_config() -> Result<()> {
+    let dir = tempdir()?;
+    let stdout = pinns_dry_run(
+        dir.path(),
+        &[
+            ("PINNS_FILENAME", "pinned"),
+            ("PINNS_NET", "true"),
+            ("PINNS_UTS", "1"),
+        ],
+        &[],
+    )?;
+    assert!(stdout.contains("net, uts"));
+    assert!(stdout.contains(&dir.path().join("netns/pinned").display().to_string()));
+    Ok(())
+}
+
+#[test]
+fn env_cli_takes_precedence() -> Result<()> {
+    let dir = tempdir()?;
+    let stdout = pinns_dry_run(
+        dir.path(),
+        &[("PINNS_FILENAME", "pinned"), ("PINNS_NET", "0")],
+        &["--filename", "other", "--uts"],
+    )?;
+    assert!(!stdout.contains("netns"));
+    assert!(stdout.contains(&dir.path().join("utsns/other").display().to_string()));
+    Ok(())
+}
+
+#[test]
+fn env_ignored_by_builder() -> Result<()> {
+    // The environment is shared by all tests of the process, which is why the
+    // test binary runs the check within a child process
+    if var_os("PINNS_TEST_BUILDER").is_some() {
+        let mut c = Config::builder().build();
+        c.merge_env()?;
+        assert_eq!(c.dir(), &temp_dir());
+        assert!(!c.timing());
+        return Ok(());
+    }
+
+    let dir = tempdir()?;
+    let status = Command::new(current_exe()?)
+        .args(&["--exact", "env_ignored_by_builder", "--test-threads", "1"])
+        .env("PINNS_TEST_BUILDER", "1")
+        .env("PINNS_DIR", dir.path())
+        .env("PINNS_TIMING", "true")
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn env_failed_invalid_flag() -> Result<()> {
+    let dir = tempdir()?;
+    let status = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .env("PINNS_DIR", dir.path())
+        .env("PINNS_NET", "maybe")
+        .arg("--dry-run")
+        .status()?;
+    assert!(!status.success());
+    Ok(())
+}
+
+#[test]
+fn env_print_config() -> Result<()> {
+    let dir = tempdir()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .env("PINNS_DIR", dir.path())
+        .env("PINNS_FILENAME", "pinned")
+        .env("PINNS_NET", "1")
+        .arg("--print-config")
+        .output()?;
+    assert!(output.status.success());
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(config["dir"], dir.path().display().to_string());
+    assert_eq!(config["filename"], "pinned");
+    assert_eq!(config["namespaces"], serde_json::json!(["net"]));
+    assert!(!dir.path().join("netns").exists());
+    Ok(())
+}
@@ -0,0 +1,47 @@
+#[macro_use]
+mod common;
+
+use anyhow::{format_err, Result};
+use std::process::Command;
+use tempfile::tempdir;
+
+/// The capability set of the status field, like `CapEff:	000001ffffffffff`
+fn capabilities(status: &str, field: &str) -> Result<u64> {
+    let value = status
+        .lines()
+        .find(|x| x.starts_with(field))
+        .map(|x| &x[field.len()..])
+        .ok_or_else(|| format_err!("no {} in status", field))?;
+    Ok(u64::from_str_radix(value.trim(), 16)?)
+}
+
+#[test]
+fn exec_cap_drop_net_raw() -> Result<()> {
+    // Dropping from the bounding set requires CAP_SETPCAP
+    skip_unless_root!();
+
+    let dir = tempdir()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "--uts", "exec"])
+        .args(&["--cap-drop", "CAP_NET_RAW", "cat", "/proc/self/status"])
+        .output()?;
+    assert!(output.status.success());
+
+    // CAP_NET_RAW is capability number 13
+    let status = String::from_utf8(output.stdout)?;
+    let net_raw = 1 << 13;
+    assert_eq!(capabilities(&status, "CapBnd:")? & net_raw, 0);
+    assert_eq!(capabilities(&status, "CapEff:")? & net_raw, 0);
+    // Only the dropped capability is gone
+    assert_ne!(capabilities(&status, "CapEff:")?, 0);
+
+    assert!(Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["unpin", "--dir"])
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "uts"])
+        .status()?
+        .success());
+    Ok(())
+}
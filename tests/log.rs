@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::{fs::read_to_string, process::Command};
+use tempfile::tempdir;
+
+#[test]
+fn log_file_appends_messages() -> Result<()> {
+    let dir = tempdir()?;
+    let log_file = dir.path().join("pinns.log");
+    for _ in 0..2 {
+        let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+            .arg("--dir")
+            .arg(dir.path())
+            .arg("--log-file")
+            .arg(&log_file)
+            .args(&["--log-level", "debug", "--net", "--dry-run"])
+            .output()?;
+        assert!(output.status.success());
+        assert!(output.stderr.is_empty());
+    }
+
+    let log = read_to_string(&log_file)?;
+    assert_eq!(log.matches("kernel supported namespaces").count(), 2);
+    Ok(())
+}
+
+#[test]
+fn log_file_falls_back_to_stderr() -> Result<()> {
+    let dir = tempdir()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .arg("--log-file")
+        .arg(dir.path().join("missing/pinns.log"))
+        .args(&["--net", "--dry-run"])
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("unable to open log file"));
+    Ok(())
+}
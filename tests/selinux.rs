@@ -0,0 +1,43 @@
+#[macro_use]
+mod common;
+
+use anyhow::Result;
+use std::{fs::read_to_string, process::Command};
+use tempfile::tempdir;
+
+fn selinux_enabled() -> Result<bool> {
+    Ok(read_to_string("/proc/self/mountinfo")?
+        .lines()
+        .filter_map(|x| x.split(" - ").nth(1))
+        .any(|x| x.starts_with("selinuxfs ")))
+}
+
+#[test]
+fn selinux_label_skipped_if_disabled() -> Result<()> {
+    // The label only gets skipped after pinning, which requires root
+    skip_unless_root!();
+    if selinux_enabled()? {
+        return Ok(());
+    }
+
+    let dir = tempdir()?;
+    let label = "system_u:object_r:container_file_t:s0";
+    let output = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "--uts", "--selinux-label", label])
+        .output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    let warning = format!("SELinux is disabled, not applying label {}", label);
+    assert!(stderr.contains(&warning));
+    assert!(dir.path().join("utsns").join("pinned").exists());
+
+    assert!(Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["unpin", "--dir"])
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "uts"])
+        .status()?
+        .success());
+    Ok(())
+}
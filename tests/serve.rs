@@ -0,0 +1,143 @@
+use anyhow::Result;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::{Pid, Uid},
+};
+use std::{
+    fs::metadata,
+    io::{BufRead, BufReader, Write},
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    path::Path,
+    process::Command,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tempfile::tempdir;
+
+fn request(socket: &Path, request: &str) -> Result<serde_json::Value> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[test]
+fn serve_requests_until_sigterm() -> Result<()> {
+    let dir = tempdir()?;
+    let socket = dir.path().join("pinns.sock");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["serve", "--socket"])
+        .arg(&socket)
+        .arg("--dir")
+        .arg(dir.path())
+        .spawn()?;
+
+    let start = Instant::now();
+    while !socket.exists() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill()?;
+            panic!("socket did not get created");
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(metadata(&socket)?.permissions().mode() & 0o777, 0o600);
+
+    let response = request(&socket, r#"{"namespaces": []}"#)?;
+    assert_eq!(response["error"], "invalid configuration (exit code 2)");
+
+    // Requests must not pin below directories the daemon was not started for
+    for outside in &["/", "../outside"] {
+        let req = format!(r#"{{"namespaces": ["uts"], "dir": "{}"}}"#, outside);
+        let response = request(&socket, &req)?;
+        assert_eq!(response["error"], "invalid configuration (exit code 2)");
+    }
+
+    // Workers of unprivileged daemons fail to unshare the namespaces
+    if Uid::effective().is_root() {
+        let response = request(&socket, r#"{"namespaces": ["uts"], "filename": "pinned"}"#)?;
+        let pinned = dir.path().join("utsns").join("pinned");
+        assert_eq!(response["paths"]["uts"], pinned.display().to_string());
+        assert!(Command::new(env!("CARGO_BIN_EXE_pinns"))
+            .args(&["unpin", "--filename", "pinned", "--dir"])
+            .arg(dir.path())
+            .arg("uts")
+            .status()?
+            .success());
+    }
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
+    assert!(child.wait()?.success());
+    assert!(!socket.exists());
+    Ok(())
+}
+
+#[test]
+fn serve_exits_after_idle_timeout() -> Result<()> {
+    let dir = tempdir()?;
+    let socket = dir.path().join("pinns.sock");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["serve", "--idle-timeout", "1", "--socket"])
+        .arg(&socket)
+        .arg("--dir")
+        .arg(dir.path())
+        .spawn()?;
+
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill()?;
+            panic!("server did not exit after being idle");
+        }
+        sleep(Duration::from_millis(10));
+    }
+    assert!(child.wait()?.success());
+    assert!(!socket.exists());
+    Ok(())
+}
+
+#[test]
+fn serve_idle_timeout_waits_for_requests_in_flight() -> Result<()> {
+    let dir = tempdir()?;
+    let socket = dir.path().join("pinns.sock");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .args(&["serve", "--idle-timeout", "1", "--socket"])
+        .arg(&socket)
+        .arg("--dir")
+        .arg(dir.path())
+        .spawn()?;
+
+    let start = Instant::now();
+    while !socket.exists() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill()?;
+            panic!("socket did not get created");
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    // The worker waits for the request line beyond the idle timeout
+    let mut stream = UnixStream::connect(&socket)?;
+    sleep(Duration::from_secs(2));
+    assert!(child.try_wait()?.is_none());
+
+    writeln!(stream, r#"{{"namespaces": []}}"#)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: serde_json::Value = serde_json::from_str(&line)?;
+    assert_eq!(response["error"], "invalid configuration (exit code 2)");
+
+    // Without resetting the timer the server stops shortly after the worker
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() > Duration::from_millis(900) {
+            child.kill()?;
+            panic!("server did not exit after the last worker");
+        }
+        sleep(Duration::from_millis(10));
+    }
+    assert!(child.wait()?.success());
+    assert!(!socket.exists());
+    Ok(())
+}
@@ -0,0 +1,138 @@
+#[macro_use]
+mod common;
+
+use anyhow::Result;
+use libc::SYS_read;
+use nix::{
+    fcntl::{open, OFlag},
+    sys::{
+        signal::{kill, Signal},
+        stat::Mode,
+    },
+    unistd::{close, mkfifo, write, Pid},
+};
+use std::{
+    fs::{create_dir, read_dir, read_to_string},
+    os::unix::fs::symlink,
+    path::Path,
+    process::{Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tempfile::tempdir;
+
+#[test]
+fn exec_cleanup_on_sigterm() -> Result<()> {
+    // Only a pinned namespace shows whether the cleanup works
+    skip_unless_root!();
+
+    let dir = tempdir()?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "--net", "exec", "sleep", "60"])
+        .spawn()?;
+
+    let pinned = dir.path().join("netns").join("pinned");
+    let start = Instant::now();
+    while !pinned.exists() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill()?;
+            panic!("namespace did not get pinned");
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
+    assert!(!child.wait()?.success());
+    assert!(!pinned.exists());
+    Ok(())
+}
+
+#[test]
+fn pin_cleanup_on_sigterm_during_binding() -> Result<()> {
+    // The FIFO blocks in between unsharing and binding, both as root only
+    skip_unless_root!();
+
+    // The proc path mirrors /proc, except for the cgroup of the unsharing
+    // thread being a FIFO. Reading it blocks pinns after installing the signal
+    // handlers, but before binding the namespaces.
+    let proc_dir = tempdir()?;
+    for entry in read_dir("/proc")? {
+        let name = entry?.file_name();
+        if name != "thread-self" {
+            symlink(Path::new("/proc").join(&name), proc_dir.path().join(&name))?;
+        }
+    }
+    let thread_self = proc_dir.path().join("thread-self");
+    create_dir(&thread_self)?;
+    symlink("/proc/thread-self/ns", thread_self.join("ns"))?;
+    let cgroup = thread_self.join("cgroup");
+    mkfifo(&cgroup, Mode::S_IRUSR | Mode::S_IWUSR)?;
+
+    let dir = tempdir()?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .arg("--proc-path")
+        .arg(proc_dir.path())
+        .args(&["--filename", "pinned", "--cgroup", "--uts"])
+        .spawn()?;
+
+    // Opening the FIFO without blocking only succeeds once pinns reads it
+    let start = Instant::now();
+    let fd = loop {
+        match open(&cgroup, OFlag::O_WRONLY | OFlag::O_NONBLOCK, Mode::empty()) {
+            Ok(fd) => break fd,
+            Err(_) if start.elapsed() < Duration::from_secs(10) => sleep(Duration::from_millis(10)),
+            Err(e) => {
+                child.kill()?;
+                return Err(e.into());
+            }
+        }
+    };
+
+    // The signal is pending before pinns continues, which then gets
+    // interrupted after binding the first namespace
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
+    write(fd, b"0::/\n")?;
+    close(fd)?;
+
+    assert!(!child.wait()?.success());
+    assert_eq!(read_dir(dir.path())?.count(), 0);
+    let mountinfo = read_to_string("/proc/self/mountinfo")?;
+    assert!(!mountinfo.contains(&dir.path().display().to_string()));
+    Ok(())
+}
+
+#[test]
+fn pin_interrupted_on_sigterm_while_reading_stdin() -> Result<()> {
+    let dir = tempdir()?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinns"))
+        .arg("--dir")
+        .arg(dir.path())
+        .args(&["--filename", "pinned", "--from-stdin"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+
+    // The signal handlers are installed once pinns blocks in reading stdin
+    let syscall = format!("/proc/{}/syscall", child.id());
+    let reading = format!("{} 0x0 ", SYS_read);
+    let start = Instant::now();
+    while !read_to_string(&syscall)?.starts_with(&reading) {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill()?;
+            panic!("pinns did not read stdin");
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    // Keeping stdin open would block pinns forever without handling the signal
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
+    let status = child.wait()?;
+    drop(stdin);
+    assert!(status.code().map_or(false, |x| x != 0));
+    assert_eq!(read_dir(dir.path())?.count(), 0);
+    Ok(())
+}